@@ -0,0 +1,113 @@
+use std::io::ErrorKind;
+use std::sync::PoisonError;
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+use symphonia::core::errors::Error as SymphoniaError;
+use thiserror::Error;
+
+use crate::music::LibraryError;
+use crate::player::PlayerError;
+
+/// Ошибка команд. Интерфейс получает её как `{ code, messageKey, details }`:
+/// по коду можно ветвиться, текст переводится по ключу, `details` — для журнала.
+#[derive(Error, Debug)]
+pub enum MusicError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Player error: {0}")]
+    Player(#[from] PlayerError),
+    #[error("Library error: {0}")]
+    Library(#[from] LibraryError),
+    #[error("Settings store error: {0}")]
+    Store(#[from] tauri_plugin_store::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Tauri error: {0}")]
+    Tauri(#[from] tauri::Error),
+    #[error("Background task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+    #[error("Waveform error: {0}")]
+    Waveform(String),
+    #[error("No track is loaded")]
+    NoActiveTrack,
+    #[error("File not found: {0}")]
+    FileNotFound(String),
+    #[error("Equalizer preset not found: {0}")]
+    PresetNotFound(String),
+    #[error("Mutex poisoned")]
+    MutexPoisoned,
+}
+
+impl<T> From<PoisonError<T>> for MusicError {
+    fn from(_: PoisonError<T>) -> Self {
+        MusicError::MutexPoisoned
+    }
+}
+
+impl MusicError {
+    /// Стабильный код ошибки; из него же строится ключ перевода
+    pub fn code(&self) -> &'static str {
+        match self {
+            MusicError::Io(err) => io_code(err),
+            MusicError::Player(err) => match err {
+                PlayerError::Io(err) => io_code(err),
+                PlayerError::Symphonia(SymphoniaError::Unsupported(_)) => "unsupportedFormat",
+                PlayerError::Symphonia(_) => "decode",
+                PlayerError::Cpal(_) | PlayerError::StreamPlay(_) => "outputStream",
+                PlayerError::UnsupportedFormat => "unsupportedFormat",
+                PlayerError::NoTracks => "noAudioTracks",
+                PlayerError::UnsupportedCodec => "unsupportedCodec",
+                PlayerError::MutexPoisoned => "mutexPoisoned",
+                PlayerError::SeekFailed => "seekFailed",
+                PlayerError::OutOfRange => "outOfRange",
+                PlayerError::NoOutputDevice => "noOutputDevice",
+                PlayerError::Disconnected => "playerStopped",
+            },
+            MusicError::Library(_) => "library",
+            MusicError::Store(_) | MusicError::Json(_) => "settings",
+            MusicError::Tauri(_) | MusicError::Task(_) => "internal",
+            MusicError::Waveform(_) => "waveform",
+            MusicError::NoActiveTrack => "noActiveTrack",
+            MusicError::FileNotFound(_) => "fileNotFound",
+            MusicError::PresetNotFound(_) => "presetNotFound",
+            MusicError::MutexPoisoned => "mutexPoisoned",
+        }
+    }
+}
+
+fn io_code(err: &std::io::Error) -> &'static str {
+    match err.kind() {
+        ErrorKind::NotFound => "fileNotFound",
+        ErrorKind::PermissionDenied => "permissionDenied",
+        _ => "io",
+    }
+}
+
+impl Serialize for MusicError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let code = self.code();
+        let mut error = serializer.serialize_struct("MusicError", 3)?;
+        error.serialize_field("code", code)?;
+        error.serialize_field("messageKey", &format!("errors.{code}"))?;
+        error.serialize_field("details", &self.to_string())?;
+        error.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_code_and_message_key() {
+        let error = MusicError::Player(PlayerError::OutOfRange);
+        let json = serde_json::to_value(&error).unwrap();
+        assert_eq!(json["code"], "outOfRange");
+        assert_eq!(json["messageKey"], "errors.outOfRange");
+        assert_eq!(json["details"], "Player error: Value out of range");
+
+        let missing = std::io::Error::new(ErrorKind::NotFound, "gone");
+        assert_eq!(MusicError::from(missing).code(), "fileNotFound");
+    }
+}
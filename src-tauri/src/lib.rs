@@ -3,134 +3,1175 @@
     windows_subsystem = "windows"
 )]
 
+mod error;
 mod music;
 mod player;
+mod queue;
+mod session;
 
 use std::env;
 
 use std::{
-    path::Path,
-    sync::{Arc, Mutex},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    time::Duration,
 };
 
-use tauri::{Emitter, Manager, State};
+use serde::Serialize;
+use tauri::http::StatusCode;
+use tauri::{AppHandle, Emitter, Manager, RunEvent, State};
+use tauri_plugin_store::StoreExt;
 
-use crate::music::{extract_waveform, extract_waveform_streaming, get_music, Track};
-use crate::player::{Player, PlayerError};
+use crate::error::MusicError;
+use crate::music::{
+    edit_tracks, extract_waveform, extract_waveform_streaming, read_legacy_cache, scan_library,
+    serve_cover, write_replay_gain, CoverCache, CoverCacheStats, CoverRules, Library,
+    LibraryWatcher, ScanJobs, TagEdit, TagEditSummary, Track, TrackLoudness,
+};
+use crate::player::{
+    list_hosts, ChannelLayout, Crossfade, EndReason, EqSettings, GainStore, HostInfo, Loudness,
+    OutputDevice, OverrideScope, PlaybackState, Player, PlayerError, PlayerEvent, PlayerSettings,
+    Position, ReplayGainSettings, ResampleQuality, SpeedMode, STATE_INTERVALS,
+};
+
+use crate::queue::{Queue, QueueState, RepeatMode};
+use crate::session::{Session, SessionStore};
 
 use tauri_plugin_single_instance::init as single_instance;
-use thiserror::Error;
 
-#[derive(Error, Debug)]
-pub enum MusicError {
-    #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
-    #[error("Player error: {0}")]
-    Player(#[from] PlayerError),
-    #[error("Mutex poisoned")]
-    MutexPoisoned,
-}
+const SETTINGS_STORE: &str = "settings.json";
+const OUTPUT_DEVICE_KEY: &str = "outputDevice";
+const EQUALIZER_KEY: &str = "equalizer";
+const REPLAY_GAIN_KEY: &str = "replayGain";
+const STATE_INTERVAL_KEY: &str = "playbackStateInterval";
+const CROSSFADE_KEY: &str = "crossfade";
+const RESAMPLE_QUALITY_KEY: &str = "resampleQuality";
+const DITHER_KEY: &str = "dither";
+const SPEED_MODE_KEY: &str = "speedMode";
+const PITCH_KEY: &str = "pitch";
+/// Кэш громкости прежних версий, переносится в индекс библиотеки
+const LOUDNESS_CACHE: &str = "loudness.json";
+const LIBRARY_DB: &str = "library.db";
+const COVER_CACHE: &str = "covers";
+const COVER_BUDGET_KEY: &str = "coverCacheBudget";
+const COVER_RULES_KEY: &str = "coverRules";
+const RESUME_SESSION_KEY: &str = "resumeSession";
+const SESSION_FILE: &str = "session.json";
+/// Как часто сеанс сохраняется на случай аварийного завершения
+const SESSION_INTERVAL: Duration = Duration::from_secs(10);
 
-#[derive(Default)]
 struct AppState {
     player: Option<Arc<Mutex<Player>>>,
     current_path: Option<String>,
+    settings: PlayerSettings,
+    events: Sender<PlayerEvent>,
+    gains: Arc<dyn GainStore>,
+    queue: Queue,
+    /// Что отдано очереди плеера для перехода без паузы
+    enqueued: Option<String>,
+}
+
+impl AppState {
+    fn new(
+        settings: PlayerSettings,
+        events: Sender<PlayerEvent>,
+        gains: Arc<dyn GainStore>,
+    ) -> Self {
+        AppState {
+            player: None,
+            current_path: None,
+            settings,
+            events,
+            gains,
+            queue: Queue::default(),
+            enqueued: None,
+        }
+    }
+
+    /// Плеер, если хоть один трек уже загружен
+    fn player(&self) -> Result<MutexGuard<'_, Player>, MusicError> {
+        let player = self.player.as_ref().ok_or(MusicError::NoActiveTrack)?;
+        Ok(player.lock()?)
+    }
+
+    /// Загружает трек в плеер; поток вывода создаётся только при первом запуске
+    fn load(&mut self, path: &str) -> Result<(), MusicError> {
+        if let Some(player) = &self.player {
+            player.lock()?.load(path)?;
+        } else {
+            let player = Player::new(
+                path,
+                self.settings.clone(),
+                self.events.clone(),
+                self.gains.clone(),
+                false,
+            )?;
+            self.player = Some(Arc::new(Mutex::new(player)));
+        }
+        self.current_path = Some(path.to_string());
+        // загрузка очищает очередь плеера
+        self.enqueued = None;
+        Ok(())
+    }
+
+    /// Отдаёт плееру трек, который очередь включит следующим: декодер
+    /// перейдёт на него сам, без паузы или с кроссфейдом
+    fn sync_next(&mut self) {
+        let next = match &self.current_path {
+            Some(path) if self.queue.follows(path) => self.queue.peek_next(),
+            _ => None,
+        };
+        if next == self.enqueued {
+            return;
+        }
+        let Some(Ok(player)) = self.player.as_ref().map(|player| player.lock()) else {
+            return;
+        };
+        let result = player.clear_queue().and_then(|()| match &next {
+            Some(path) => player.enqueue(path),
+            None => Ok(()),
+        });
+        self.enqueued = match result {
+            Ok(()) => next,
+            Err(err) => {
+                // без подготовки трек включится по окончании текущего
+                eprintln!("Failed to prepare next track: {err}");
+                None
+            }
+        };
+    }
+
+    /// Снимок для сохранения сеанса
+    fn session(&self) -> Session {
+        let mut session = Session {
+            path: self.current_path.clone(),
+            queue: self.queue.save(),
+            ..Session::default()
+        };
+        if let Some(Ok(player)) = self.player.as_ref().map(|player| player.lock()) {
+            let state = player.state();
+            // слышимый трек может отставать от загруженного при переходе
+            if state.path.is_some() {
+                session.path = state.path;
+            }
+            // доигравший трек при следующем запуске начнётся сначала
+            if !state.ended {
+                session.position = state.position;
+            }
+            session.volume = state.volume;
+            session.speed = state.speed;
+        }
+        session
+    }
+
+    /// Открывает трек прошлого сеанса на паузе с той же позиции
+    fn restore(&mut self, session: Session) -> Result<(), MusicError> {
+        self.queue.restore(session.queue);
+        let Some(path) = session.path else {
+            return Ok(());
+        };
+        if !Path::new(&path).is_file() {
+            return Err(MusicError::FileNotFound(path));
+        }
+
+        let player = Player::new(
+            &path,
+            self.settings.clone(),
+            self.events.clone(),
+            self.gains.clone(),
+            true,
+        )?;
+        player.set_volume(session.volume.clamp(0.0, 1.0))?;
+        player.set_speed(session.speed)?;
+        if session.position > 0.0 {
+            player.seek(session.position as f32)?;
+        }
+        self.player = Some(Arc::new(Mutex::new(player)));
+        self.current_path = Some(path);
+        self.sync_next();
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeviceLostPayload {
+    device: String,
+    fallback: String,
 }
 
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TrackEndedPayload {
+    path: String,
+    reason: EndReason,
+    /// Трек, который играет теперь; `None` — воспроизведение остановилось
+    next: Option<String>,
+}
+
+/// Обновляет индекс библиотеки по папкам и возвращает их треки.
+/// Теги перечитываются только у новых и изменившихся файлов,
+/// дальше за папками следит наблюдатель.
+#[tauri::command]
+async fn get_musics(app: AppHandle, paths: Vec<String>) -> Result<Vec<Track>, MusicError> {
+    tokio::task::spawn_blocking(move || {
+        add_library_roots(&app, &paths)?;
+        let library = app.state::<Mutex<Library>>();
+        scan_library(&library, &paths, &AtomicBool::new(false), |_| {})?;
+        let library = library.lock()?;
+        Ok(library.tracks_under(&paths)?)
+    })
+    .await?
+}
+
+/// Запускает сканирование папок в фоне: все найденные треки приходят
+/// событиями `library-scan-batch`, итог — `library-scan-finished`
 #[tauri::command]
-fn get_musics(paths: Vec<String>) -> Vec<Track> {
-    get_music(paths)
+fn start_library_scan(app: AppHandle, paths: Vec<String>) -> Result<(), MusicError> {
+    add_library_roots(&app, &paths)?;
+    let cancel = app.state::<ScanJobs>().start();
+
+    std::thread::Builder::new()
+        .name("musa-scan".to_string())
+        .spawn(move || {
+            let library = app.state::<Mutex<Library>>();
+            let result = scan_library(&library, &paths, &cancel, |batch| {
+                let _ = app.emit("library-scan-batch", batch);
+            });
+            app.state::<ScanJobs>().finish(&cancel);
+            match result {
+                Ok(summary) => {
+                    let _ = app.emit("library-scan-finished", summary);
+                }
+                // в том же виде, что ошибки команд: {code, messageKey, details}
+                Err(err) => {
+                    if let Ok(error) = serde_json::to_value(MusicError::from(err)) {
+                        let _ = app.emit("library-scan-failed", error);
+                    }
+                }
+            }
+        })?;
+    Ok(())
 }
+
+/// Останавливает фоновое сканирование; `false`, если оно не шло
 #[tauri::command]
-async fn set_music(state: State<'_, Mutex<AppState>>, path: String) -> Result<(), String> {
-    if !Path::new(&path).exists() {
-        return Err(MusicError::Io(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "File does not exist",
-        ))
-        .to_string());
+fn cancel_library_scan(jobs: State<'_, ScanJobs>) -> bool {
+    jobs.cancel()
+}
+
+/// Запоминает папки библиотеки и начинает следить за ними
+fn add_library_roots(app: &AppHandle, paths: &[String]) -> Result<(), MusicError> {
+    app.state::<Mutex<Library>>().lock()?.add_roots(paths)?;
+    if let Ok(mut watcher) = app.state::<Mutex<LibraryWatcher>>().lock() {
+        watcher.watch(paths);
     }
+    Ok(())
+}
+
+/// Обновляет индекс по путям от наблюдателя и сообщает интерфейсу
+fn on_library_change(app: &AppHandle, paths: Vec<PathBuf>) {
+    let library = app.state::<Mutex<Library>>();
+    let Ok(mut library) = library.lock() else {
+        return;
+    };
+    let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    match library.refresh(&paths) {
+        Ok(changes) if !changes.is_empty() => {
+            let _ = app.emit("library-changed", changes);
+        }
+        Ok(_) => {}
+        Err(err) => eprintln!("Failed to update library: {err}"),
+    }
+}
+
+/// Треки из индекса без обхода файловой системы
+#[tauri::command]
+fn get_library(
+    library: State<'_, Mutex<Library>>,
+    paths: Vec<String>,
+) -> Result<Vec<Track>, MusicError> {
+    let library = library.lock()?;
+    Ok(library.tracks_under(&paths)?)
+}
+
+#[tauri::command]
+fn search_library(
+    library: State<'_, Mutex<Library>>,
+    query: String,
+) -> Result<Vec<Track>, MusicError> {
+    let library = library.lock()?;
+    Ok(library.search(&query)?)
+}
+
+#[tauri::command]
+fn get_library_track(
+    library: State<'_, Mutex<Library>>,
+    path: String,
+) -> Result<Option<Track>, MusicError> {
+    let library = library.lock()?;
+    Ok(library.track(&path)?)
+}
+
+/// Меняет теги одного или нескольких треков и обновляет их в библиотеке
+#[tauri::command]
+async fn edit_tags(
+    app: AppHandle,
+    paths: Vec<String>,
+    edit: TagEdit,
+) -> Result<TagEditSummary, MusicError> {
+    tokio::task::spawn_blocking(move || {
+        let library = app.state::<Mutex<Library>>();
+        Ok(edit_tracks(&library, &paths, &edit)?)
+    })
+    .await?
+}
+
+#[tauri::command]
+async fn set_music(state: State<'_, Mutex<AppState>>, path: String) -> Result<(), MusicError> {
+    if !Path::new(&path).is_file() {
+        return Err(MusicError::FileNotFound(path));
+    }
+    let mut state = state.lock()?;
+    state.load(&path)?;
+    state.sync_next();
+    Ok(())
+}
+
+/// После любого изменения очереди: готовит следующий трек и сообщает интерфейсу
+fn queue_changed(app: &AppHandle, state: &mut AppState) {
+    state.sync_next();
+    let _ = app.emit("queue-changed", state.queue.state());
+}
+
+/// Загружает выбранный очередью трек; `None` — очередь кончилась
+fn play_from_queue(
+    app: &AppHandle,
+    state: &mut AppState,
+    path: Option<String>,
+) -> Result<Option<String>, MusicError> {
+    if let Some(path) = &path {
+        state.load(path)?;
+    }
+    queue_changed(app, state);
+    Ok(path)
+}
+
+#[tauri::command]
+fn get_queue(state: State<'_, Mutex<AppState>>) -> Result<QueueState, MusicError> {
+    let state = state.lock()?;
+    Ok(state.queue.state())
+}
+
+/// Заменяет очередь списком и начинает играть трек `start`
+#[tauri::command]
+async fn set_queue(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    paths: Vec<String>,
+    start: usize,
+) -> Result<Option<String>, MusicError> {
+    let mut state = state.lock()?;
+    let path = state.queue.replace(paths, start).map(str::to_string);
+    play_from_queue(&app, &mut state, path)
+}
+
+#[tauri::command]
+async fn queue_add(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    paths: Vec<String>,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.add(paths);
+    queue_changed(&app, &mut state);
+    Ok(())
+}
+
+#[tauri::command]
+async fn queue_play_next(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    paths: Vec<String>,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.play_next(paths);
+    queue_changed(&app, &mut state);
+    Ok(())
+}
+
+/// Удаляет элемент очереди; играющий трек доигрывает до конца
+#[tauri::command]
+async fn queue_remove(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    id: u64,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.remove(id);
+    queue_changed(&app, &mut state);
+    Ok(())
+}
+
+#[tauri::command]
+async fn queue_move(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    id: u64,
+    to: usize,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.move_to(id, to);
+    queue_changed(&app, &mut state);
+    Ok(())
+}
 
-    std::fs::File::open(&path).expect("File does not exist");
+#[tauri::command]
+async fn queue_clear(app: AppHandle, state: State<'_, Mutex<AppState>>) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.clear();
+    queue_changed(&app, &mut state);
+    Ok(())
+}
 
-    let mut state = state
-        .lock()
-        .map_err(|_| MusicError::MutexPoisoned)
-        .expect("State is poisoned");
+#[tauri::command]
+async fn queue_next(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+) -> Result<Option<String>, MusicError> {
+    let mut state = state.lock()?;
+    let path = state.queue.next(false).map(str::to_string);
+    play_from_queue(&app, &mut state, path)
+}
 
-    let player = Player::new(&path).expect("Failed to create player");
-    state.player = Some(Arc::new(Mutex::new(player)));
-    state.current_path = Some(path);
+#[tauri::command]
+async fn queue_previous(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+) -> Result<Option<String>, MusicError> {
+    let mut state = state.lock()?;
+    let path = state.queue.previous().map(str::to_string);
+    play_from_queue(&app, &mut state, path)
+}
 
+#[tauri::command]
+async fn set_repeat(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    repeat: RepeatMode,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.set_repeat(repeat);
+    queue_changed(&app, &mut state);
     Ok(())
 }
 
 #[tauri::command]
-async fn get_time(state: State<'_, Mutex<AppState>>) -> Result<f32, String> {
-    if let Some(player) = &state.lock().unwrap().player {
-        Ok(player.lock().unwrap().current_time().unwrap())
-    } else {
-        Err("Нет активного трека".to_string())
+async fn set_shuffle(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    shuffle: bool,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    state.queue.set_shuffle(shuffle);
+    queue_changed(&app, &mut state);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_crossfade(state: State<'_, Mutex<AppState>>) -> Result<Crossfade, MusicError> {
+    Ok(state.lock()?.settings.crossfade)
+}
+
+#[tauri::command]
+async fn set_crossfade(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    crossfade: Crossfade,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_crossfade(crossfade)?;
+    } else if !crossfade.is_valid() {
+        return Err(PlayerError::OutOfRange.into());
     }
+    save_setting(&app, CROSSFADE_KEY, &crossfade)?;
+    state.settings.crossfade = crossfade;
+    Ok(())
 }
 
 #[tauri::command]
-async fn play_music(state: State<'_, Mutex<AppState>>) -> Result<(), String> {
-    if let Some(player) = &state.lock().unwrap().player {
-        player.lock().unwrap().resume();
-        Ok(())
-    } else {
-        Err("Нет активного трека".to_string())
+async fn set_resample_quality(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    quality: ResampleQuality,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_resample_quality(quality)?;
     }
+    save_setting(&app, RESAMPLE_QUALITY_KEY, &quality)?;
+    state.settings.resample_quality = quality;
+    Ok(())
 }
 
 #[tauri::command]
-async fn stop_music(state: State<'_, Mutex<AppState>>) -> Result<(), String> {
-    if let Some(player) = &state.lock().unwrap().player {
-        player.lock().unwrap().pause();
-        Ok(())
-    } else {
-        Err("Нет активного трека".to_string())
+async fn get_time(state: State<'_, Mutex<AppState>>) -> Result<f32, MusicError> {
+    let time = state.lock()?.player()?.current_time()?;
+    Ok(time)
+}
+
+/// Снимок состояния, например когда окно снова получило фокус;
+/// в остальное время оно приходит событием `playback-state`
+#[tauri::command]
+async fn get_player_state(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<Option<PlaybackState>, MusicError> {
+    let state = state.lock()?;
+    let Some(player) = &state.player else {
+        return Ok(None);
+    };
+    let player = player.lock()?;
+    Ok(Some(player.state()))
+}
+
+/// Как часто во время игры приходит `playback-state`, мс
+#[tauri::command]
+async fn set_playback_state_interval(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    interval: u64,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_state_interval(interval)?;
+    } else if !STATE_INTERVALS.contains(&interval) {
+        return Err(PlayerError::OutOfRange.into());
     }
+    save_setting(&app, STATE_INTERVAL_KEY, &interval)?;
+    state.settings.state_interval = interval;
+    Ok(())
 }
 
 #[tauri::command]
-async fn set_speed(state: State<'_, Mutex<AppState>>, speed: f32) -> Result<(), String> {
-    if let Some(player) = &state.lock().unwrap().player {
-        player.lock().unwrap().set_speed(speed);
-        Ok(())
-    } else {
-        Err("Нет активного трека".to_string())
+async fn play_music(state: State<'_, Mutex<AppState>>) -> Result<(), MusicError> {
+    state.lock()?.player()?.resume()?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn stop_music(state: State<'_, Mutex<AppState>>) -> Result<(), MusicError> {
+    state.lock()?.player()?.pause()?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_speed(state: State<'_, Mutex<AppState>>, speed: f32) -> Result<(), MusicError> {
+    state.lock()?.player()?.set_speed(speed)?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn seek_music(state: State<'_, Mutex<AppState>>, sec: f32) -> Result<(), MusicError> {
+    state.lock()?.player()?.seek(sec)?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_volume(state: State<'_, Mutex<AppState>>, volume: f32) -> Result<(), MusicError> {
+    state.lock()?.player()?.set_volume(volume)?;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_dither(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    enabled: bool,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_dither(enabled)?;
     }
+    save_setting(&app, DITHER_KEY, &enabled)?;
+    state.settings.dither = enabled;
+    Ok(())
 }
 
 #[tauri::command]
-async fn seek_music(state: State<'_, Mutex<AppState>>, sec: f32) -> Result<(), String> {
-    if let Some(player) = &state.lock().unwrap().player {
-        player.lock().unwrap().seek(sec);
-        Ok(())
-    } else {
-        Err("Нет активного трека".to_string())
+async fn set_speed_mode(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    mode: SpeedMode,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_speed_mode(mode)?;
     }
+    save_setting(&app, SPEED_MODE_KEY, &mode)?;
+    state.settings.speed_mode = mode;
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_pitch(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    semitones: f32,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_pitch(semitones)?;
+    } else if !(-12.0..=12.0).contains(&semitones) {
+        return Err(PlayerError::OutOfRange.into());
+    }
+    save_setting(&app, PITCH_KEY, &semitones)?;
+    state.settings.pitch = semitones;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_replay_gain(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<ReplayGainSettings, MusicError> {
+    Ok(state.lock()?.settings.replay_gain)
+}
+
+#[tauri::command]
+async fn set_replay_gain(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    replay_gain: ReplayGainSettings,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.set_replay_gain(replay_gain)?;
+    } else if !(-15.0..=15.0).contains(&replay_gain.preamp) {
+        return Err(PlayerError::OutOfRange.into());
+    }
+    save_setting(&app, REPLAY_GAIN_KEY, &replay_gain)?;
+    state.settings.replay_gain = replay_gain;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_output_devices() -> Vec<HostInfo> {
+    list_hosts()
+}
+
+#[tauri::command]
+async fn set_output_device(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    device: OutputDevice,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    if let Some(player) = &state.player {
+        player.lock()?.switch_device(&device)?;
+    }
+
+    // выбор запоминаем между запусками
+    save_setting(&app, OUTPUT_DEVICE_KEY, &device)?;
+
+    state.settings.output_device = device;
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_equalizer(state: State<'_, Mutex<AppState>>) -> Result<EqSettings, MusicError> {
+    Ok(state.lock()?.settings.equalizer.clone())
+}
+
+#[tauri::command]
+async fn get_equalizer_presets(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<Vec<String>, MusicError> {
+    Ok(state.lock()?.settings.equalizer.preset_names())
+}
+
+#[tauri::command]
+async fn set_equalizer(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    equalizer: EqSettings,
+) -> Result<(), MusicError> {
+    update_equalizer(&app, &state, |eq| {
+        *eq = equalizer;
+        Ok(())
+    })
 }
 
 #[tauri::command]
-async fn set_volume(state: State<'_, Mutex<AppState>>, volume: f32) -> Result<(), String> {
-    if let Some(player) = &state.lock().unwrap().player {
-        player.lock().unwrap().set_volume(volume);
+async fn apply_equalizer_preset(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    name: String,
+) -> Result<(), MusicError> {
+    update_equalizer(&app, &state, |eq| {
+        eq.current = eq
+            .preset(&name)
+            .ok_or_else(|| MusicError::PresetNotFound(name.clone()))?;
         Ok(())
-    } else {
-        Err("Нет активного трека".to_string())
+    })
+}
+
+#[tauri::command]
+async fn save_equalizer_preset(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    name: String,
+) -> Result<(), MusicError> {
+    update_equalizer(&app, &state, |eq| {
+        eq.presets.insert(name, eq.current.clone());
+        Ok(())
+    })
+}
+
+#[tauri::command]
+async fn set_equalizer_override(
+    app: AppHandle,
+    state: State<'_, Mutex<AppState>>,
+    scope: OverrideScope,
+    key: String,
+    preset: Option<String>,
+) -> Result<(), MusicError> {
+    update_equalizer(&app, &state, |eq| {
+        eq.set_override(scope, key, preset);
+        Ok(())
+    })
+}
+
+/// Меняет настройки эквалайзера, передаёт их плееру и сохраняет
+fn update_equalizer(
+    app: &AppHandle,
+    state: &State<'_, Mutex<AppState>>,
+    change: impl FnOnce(&mut EqSettings) -> Result<(), MusicError>,
+) -> Result<(), MusicError> {
+    let mut state = state.lock()?;
+    let mut equalizer = state.settings.equalizer.clone();
+    change(&mut equalizer)?;
+    if !equalizer.is_valid() {
+        return Err(PlayerError::OutOfRange.into());
+    }
+
+    if let Some(player) = &state.player {
+        player.lock()?.set_equalizer(equalizer.clone())?;
+    }
+    save_setting(app, EQUALIZER_KEY, &equalizer)?;
+    state.settings.equalizer = equalizer;
+    Ok(())
+}
+
+#[tauri::command]
+fn get_resume_session(sessions: State<'_, Mutex<SessionStore>>) -> Result<bool, MusicError> {
+    let sessions = sessions.lock()?;
+    Ok(sessions.enabled())
+}
+
+/// Восстанавливать ли при запуске прошлый сеанс; выключение стирает сохранённый
+#[tauri::command]
+fn set_resume_session(
+    app: AppHandle,
+    sessions: State<'_, Mutex<SessionStore>>,
+    enabled: bool,
+) -> Result<(), MusicError> {
+    save_setting(&app, RESUME_SESSION_KEY, &enabled)?;
+    sessions.lock()?.set_enabled(enabled)?;
+    Ok(())
+}
+
+/// Сохраняет сеанс, если восстановление включено и что-то изменилось
+fn save_session(app: &AppHandle) {
+    let sessions = app.state::<Mutex<SessionStore>>();
+    let Ok(mut sessions) = sessions.lock() else {
+        return;
+    };
+    if !sessions.enabled() {
+        return;
+    }
+    let state = app.state::<Mutex<AppState>>();
+    let Ok(session) = state.lock().map(|state| state.session()) else {
+        return;
+    };
+    if let Err(err) = sessions.save(session) {
+        eprintln!("Failed to save session: {err}");
+    }
+}
+
+fn save_setting(app: &AppHandle, key: &str, value: &impl Serialize) -> Result<(), MusicError> {
+    let store = app.store(SETTINGS_STORE)?;
+    store.set(key, serde_json::to_value(value)?);
+    Ok(store.save()?)
+}
+
+#[tauri::command]
+async fn get_channel_layout(
+    state: State<'_, Mutex<AppState>>,
+) -> Result<ChannelLayout, MusicError> {
+    let layout = state.lock()?.player()?.channel_layout()?;
+    Ok(layout)
+}
+
+#[tauri::command]
+async fn get_position(state: State<'_, Mutex<AppState>>) -> Result<Position, MusicError> {
+    let position = state.lock()?.player()?.position()?;
+    Ok(position)
+}
+
+#[tauri::command]
+async fn get_wave(path: String, points: usize) -> Result<Vec<f32>, MusicError> {
+    extract_waveform_streaming(path, points)
+        .await
+        .map_err(|e| MusicError::Waveform(e.to_string()))
+}
+
+fn data_path(app: &AppHandle, name: &str) -> Result<PathBuf, MusicError> {
+    Ok(app.path().app_data_dir()?.join(name))
+}
+
+/// Ответ протокола `cover://`: оригинал `/<id>` или миниатюра `/<id>/<размер>`
+fn cover_response(path: &str) -> tauri::http::Response<Vec<u8>> {
+    match serve_cover(path) {
+        Ok((data, mime)) => tauri::http::Response::builder()
+            .header("Content-Type", mime)
+            // id — хеш содержимого, поэтому ответ никогда не меняется
+            .header("Cache-Control", "public, max-age=31536000, immutable")
+            .body(data.to_vec())
+            .unwrap_or_else(|_| cover_error(StatusCode::INTERNAL_SERVER_ERROR)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            cover_error(StatusCode::NOT_FOUND)
+        }
+        Err(err) => {
+            eprintln!("Failed to serve cover {path}: {err}");
+            cover_error(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
 
+fn cover_error(status: StatusCode) -> tauri::http::Response<Vec<u8>> {
+    let mut response = tauri::http::Response::new(Vec::new());
+    *response.status_mut() = status;
+    response
+}
+
+#[tauri::command]
+fn get_cover_cache_stats() -> Result<CoverCacheStats, MusicError> {
+    let cache = CoverCache::global().lock()?;
+    Ok(cache.stats())
+}
+
+/// Сколько байт обложек держать в памяти
+#[tauri::command]
+fn set_cover_cache_budget(app: AppHandle, budget: usize) -> Result<CoverCacheStats, MusicError> {
+    save_setting(&app, COVER_BUDGET_KEY, &budget)?;
+    let mut cache = CoverCache::global().lock()?;
+    cache.set_budget(budget);
+    Ok(cache.stats())
+}
+
+#[tauri::command]
+fn get_cover_rules() -> Result<CoverRules, MusicError> {
+    let cache = CoverCache::global().lock()?;
+    Ok(cache.rules().clone())
+}
+
+/// Правила поиска обложек-файлов; применяются к трекам, прочитанным после
+#[tauri::command]
+fn set_cover_rules(app: AppHandle, rules: CoverRules) -> Result<(), MusicError> {
+    save_setting(&app, COVER_RULES_KEY, &rules)?;
+    let mut cache = CoverCache::global().lock()?;
+    cache.set_rules(rules);
+    Ok(())
+}
+
+/// Измеряет громкость треков и альбомов по EBU R128 и сохраняет в индекс библиотеки.
+/// Возвращает, сколько треков измерено заново.
 #[tauri::command]
-async fn get_wave(path: String, points: usize) -> Result<Vec<f32>, String> {
-    let wave = extract_waveform_streaming(path, points).await;
+async fn scan_loudness(
+    app: AppHandle,
+    paths: Vec<String>,
+    write_tags: bool,
+) -> Result<usize, MusicError> {
+    tokio::task::spawn_blocking(move || {
+        let library = app.state::<Mutex<Library>>();
+        let albums = {
+            let library = library.lock()?;
+            let albums = library.group_albums(paths)?;
+            library.stale_albums(albums)?
+        };
+
+        let mut measured = music::scan_loudness(albums, |progress| {
+            let _ = app.emit("loudness-scan-progress", progress);
+        });
+        if write_tags {
+            for (path, loudness) in &mut measured {
+                if let Err(err) = write_replay_gain(path, loudness) {
+                    eprintln!("Failed to write ReplayGain tags to {path}: {err}");
+                }
+            }
+        }
+
+        library.lock()?.store_loudness(&measured)?;
+        Ok(measured.len())
+    })
+    .await?
+}
 
-    match wave {
-        Ok(w) => Ok(w),
-        Err(_) => Err("Произошла ошибка".to_string()),
+#[tauri::command]
+fn get_loudness(
+    library: State<'_, Mutex<Library>>,
+    path: String,
+) -> Result<Option<TrackLoudness>, MusicError> {
+    Ok(library.lock()?.loudness(&path)?)
+}
+
+/// Пересылает события плеера в интерфейс
+fn forward_player_events(app: AppHandle, events: Receiver<PlayerEvent>) {
+    for event in events {
+        match event {
+            PlayerEvent::DeviceLost { device } => {
+                let state = app.state::<Mutex<AppState>>();
+                let state = match state.lock() {
+                    Ok(state) => state,
+                    Err(_) => continue,
+                };
+                let Some(player) = &state.player else {
+                    continue;
+                };
+                let mut player = match player.lock() {
+                    Ok(player) => player,
+                    Err(_) => continue,
+                };
+                // ошибка могла прийти несколько раз подряд
+                if player.device_name() != device {
+                    continue;
+                }
+
+                match player.switch_device(&OutputDevice::default()) {
+                    Ok(()) => {
+                        let payload = DeviceLostPayload {
+                            device,
+                            fallback: player.device_name().to_string(),
+                        };
+                        let _ = app.emit("output-device-lost", payload);
+                    }
+                    Err(err) => {
+                        eprintln!("Failed to fall back to default device: {err}");
+                        // играть больше не на чем, очередь стоит на месте
+                        if let Ok(path) = player.current_path() {
+                            let payload = TrackEndedPayload {
+                                path,
+                                reason: EndReason::DeviceLost,
+                                next: None,
+                            };
+                            let _ = app.emit("track-ended", payload);
+                        }
+                    }
+                }
+            }
+            PlayerEvent::TrackStarted {
+                path,
+                previous,
+                reason,
+            } => {
+                let state = app.state::<Mutex<AppState>>();
+                let mut state = match state.lock() {
+                    Ok(state) => state,
+                    Err(_) => continue,
+                };
+                // трек уже переключили вручную
+                if state.current_path.as_deref() != Some(previous.as_str()) {
+                    continue;
+                }
+                let next = start_queued(&mut state, &previous, path);
+                let payload = TrackEndedPayload {
+                    path: previous,
+                    reason,
+                    next: Some(next),
+                };
+                let _ = app.emit("track-ended", payload);
+                queue_changed(&app, &mut state);
+            }
+            PlayerEvent::TrackEnded { path, reason } => {
+                let state = app.state::<Mutex<AppState>>();
+                let mut state = match state.lock() {
+                    Ok(state) => state,
+                    Err(_) => continue,
+                };
+                let (next, skipped) = advance_queue(&mut state, &path);
+
+                let ended = |path, reason| {
+                    let payload = TrackEndedPayload {
+                        path,
+                        reason,
+                        next: next.clone(),
+                    };
+                    let _ = app.emit("track-ended", payload);
+                };
+                ended(path, reason);
+                for path in skipped {
+                    ended(path, EndReason::DecodeError);
+                }
+                queue_changed(&app, &mut state);
+            }
+            PlayerEvent::State(state) => {
+                let _ = app.emit("playback-state", state);
+            }
+            PlayerEvent::DeviceFallback { requested, device } => {
+                let payload = DeviceLostPayload {
+                    device: requested,
+                    fallback: device,
+                };
+                let _ = app.emit("output-device-lost", payload);
+            }
+        }
+    }
+}
+
+/// Учитывает переход плеера с `previous` на подготовленный `started`.
+/// Если очередь успела измениться, играет то, что выбрала она.
+fn start_queued(state: &mut AppState, previous: &str, started: String) -> String {
+    state.current_path = Some(started.clone());
+    state.enqueued = None;
+    if !state.queue.follows(previous) {
+        return started;
+    }
+    match state.queue.next(true).map(str::to_string) {
+        Some(next) if next != started => match state.load(&next) {
+            Ok(()) => next,
+            Err(err) => {
+                eprintln!("Failed to play {next}: {err}");
+                started
+            }
+        },
+        _ => started,
+    }
+}
+
+/// Включает трек, следующий в очереди за доигравшим `ended`, если его
+/// не удалось подготовить заранее: очередь кончилась или файл не открылся.
+/// Возвращает, что играет теперь, и пропущенные файлы, которые не открылись;
+/// пропускается не больше одного круга очереди.
+fn advance_queue(state: &mut AppState, ended: &str) -> (Option<String>, Vec<String>) {
+    // трек переключили вручную, пока событие шло
+    if state.current_path.as_deref() != Some(ended) {
+        return (state.current_path.clone(), Vec::new());
+    }
+    // трек включали в обход очереди
+    if !state.queue.follows(ended) {
+        return (None, Vec::new());
+    }
+
+    let mut skipped = Vec::new();
+    for _ in 0..state.queue.state().tracks.len() {
+        // битый трек не повторяем даже в режиме повтора одного
+        let Some(path) = state.queue.next(skipped.is_empty()).map(str::to_string) else {
+            break;
+        };
+        match state.load(&path) {
+            Ok(()) => return (Some(path), skipped),
+            Err(err) => {
+                eprintln!("Failed to play {path}: {err}");
+                skipped.push(path);
+            }
+        }
+    }
+    (None, skipped)
+}
+
+fn load_settings(app: &AppHandle) -> PlayerSettings {
+    let mut settings = PlayerSettings::default();
+    if let Ok(store) = app.store(SETTINGS_STORE) {
+        if let Some(device) = store
+            .get(OUTPUT_DEVICE_KEY)
+            .and_then(|v| serde_json::from_value(v).ok())
+        {
+            settings.output_device = device;
+        }
+        if let Some(equalizer) = store
+            .get(EQUALIZER_KEY)
+            .and_then(|v| serde_json::from_value(v).ok())
+        {
+            settings.equalizer = equalizer;
+        }
+        if let Some(replay_gain) = store
+            .get(REPLAY_GAIN_KEY)
+            .and_then(|v| serde_json::from_value(v).ok())
+        {
+            settings.replay_gain = replay_gain;
+        }
+        if let Some(interval) = store
+            .get(STATE_INTERVAL_KEY)
+            .and_then(|v| serde_json::from_value(v).ok())
+            .filter(|interval| STATE_INTERVALS.contains(interval))
+        {
+            settings.state_interval = interval;
+        }
+        if let Some(crossfade) = store
+            .get(CROSSFADE_KEY)
+            .and_then(|v| serde_json::from_value::<Crossfade>(v).ok())
+            .filter(Crossfade::is_valid)
+        {
+            settings.crossfade = crossfade;
+        }
+        if let Some(quality) = store
+            .get(RESAMPLE_QUALITY_KEY)
+            .and_then(|v| serde_json::from_value(v).ok())
+        {
+            settings.resample_quality = quality;
+        }
+        if let Some(dither) = store.get(DITHER_KEY).and_then(|v| v.as_bool()) {
+            settings.dither = dither;
+        }
+        if let Some(mode) = store
+            .get(SPEED_MODE_KEY)
+            .and_then(|v| serde_json::from_value(v).ok())
+        {
+            settings.speed_mode = mode;
+        }
+        if let Some(pitch) = store
+            .get(PITCH_KEY)
+            .and_then(|v| serde_json::from_value::<f32>(v).ok())
+            .filter(|pitch| (-12.0..=12.0).contains(pitch))
+        {
+            settings.pitch = pitch;
+        }
+    }
+    settings
+}
+
+/// Громкость, измеренная плеером, хранится в индексе библиотеки
+struct LibraryGains(AppHandle);
+
+impl GainStore for LibraryGains {
+    fn gain(&self, path: &str) -> Option<f32> {
+        let library = self.0.try_state::<Mutex<Library>>()?;
+        let loudness = library.lock().ok()?.loudness(path);
+        match loudness {
+            Ok(loudness) => loudness.map(|loudness| Loudness::from(loudness.track).gain()),
+            Err(err) => {
+                eprintln!("Failed to read loudness of {path}: {err}");
+                None
+            }
+        }
+    }
+
+    fn store(&self, path: &str, loudness: Loudness) {
+        let (Some(library), Some(measured)) = (
+            self.0.try_state::<Mutex<Library>>(),
+            TrackLoudness::measured(path, loudness),
+        ) else {
+            return;
+        };
+        if let Ok(mut library) = library.lock() {
+            if let Err(err) = library.store_loudness(&[(path.to_string(), measured)]) {
+                eprintln!("Failed to store loudness of {path}: {err}");
+            }
+        }
     }
 }
 
@@ -145,22 +1186,105 @@ pub fn run() {
         .plugin(single_instance(|app, argv, _cwd| {
             if argv.len() > 1 {
                 println!("Received single instance event with args: {:?}", argv);
-                app.emit("open-files", argv[1..].to_vec()).unwrap();
+                let _ = app.emit("open-files", argv[1..].to_vec());
             }
         }))
+        .register_asynchronous_uri_scheme_protocol("cover", |_ctx, request, responder| {
+            // миниатюры создаются при первом запросе: не держим поток webview
+            let path = request.uri().path().to_string();
+            std::thread::spawn(move || responder.respond(cover_response(&path)));
+        })
         .setup(move |app| {
             if args.len() > 1 {
                 println!("Received setup event with args: {:?}", args);
-                app.emit("open-files", args[1..].to_vec()).unwrap();
+                app.emit("open-files", args[1..].to_vec())?;
+            }
+            let mut library = Library::open(&data_path(app.handle(), LIBRARY_DB)?)?;
+            let legacy = data_path(app.handle(), LOUDNESS_CACHE)?;
+            if legacy.is_file() {
+                match library.store_loudness(&read_legacy_cache(&legacy)) {
+                    Ok(()) => {
+                        let _ = std::fs::remove_file(&legacy);
+                    }
+                    Err(err) => eprintln!("Failed to import {}: {err}", legacy.display()),
+                }
+            }
+            let roots = library.roots()?;
+            app.manage(Mutex::new(library));
+
+            let (events, receiver) = mpsc::channel();
+            let settings = load_settings(app.handle());
+            let resume = app
+                .store(SETTINGS_STORE)
+                .ok()
+                .and_then(|store| store.get(RESUME_SESSION_KEY))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true);
+            let mut sessions = SessionStore::new(data_path(app.handle(), SESSION_FILE)?, resume);
+            let gains = Arc::new(LibraryGains(app.handle().clone()));
+            let mut state = AppState::new(settings, events, gains);
+            if let Some(session) = sessions.load() {
+                if let Err(err) = state.restore(session) {
+                    eprintln!("Failed to restore session: {err}");
+                }
             }
-            app.manage(Mutex::new(AppState::default()));
+            app.manage(Mutex::new(state));
+            app.manage(Mutex::new(sessions));
+            if let Ok(mut covers) = CoverCache::global().lock() {
+                if let Ok(dir) = app.path().app_cache_dir() {
+                    covers.set_directory(dir.join(COVER_CACHE));
+                }
+                if let Ok(store) = app.store(SETTINGS_STORE) {
+                    if let Some(budget) = store
+                        .get(COVER_BUDGET_KEY)
+                        .and_then(|v| serde_json::from_value(v).ok())
+                    {
+                        covers.set_budget(budget);
+                    }
+                    if let Some(rules) = store
+                        .get(COVER_RULES_KEY)
+                        .and_then(|v| serde_json::from_value(v).ok())
+                    {
+                        covers.set_rules(rules);
+                    }
+                }
+            }
+            let handle = app.handle().clone();
+            let mut watcher = LibraryWatcher::new(move |paths| on_library_change(&handle, paths))?;
+            watcher.watch(&roots);
+            app.manage(Mutex::new(watcher));
+            app.manage(ScanJobs::default());
+
+            let handle = app.handle().clone();
+            std::thread::spawn(move || forward_player_events(handle, receiver));
+
+            let handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(SESSION_INTERVAL);
+                save_session(&handle);
+            });
             Ok(())
         })
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
             get_musics, set_music, play_music, stop_music, set_volume, set_speed, seek_music,
-            get_wave, get_time
+            get_wave, get_time, get_crossfade, set_crossfade, set_resample_quality, set_dither,
+            set_speed_mode, set_pitch, get_channel_layout, get_position, get_output_devices,
+            set_output_device, get_equalizer, get_equalizer_presets, set_equalizer,
+            apply_equalizer_preset, save_equalizer_preset, set_equalizer_override, get_replay_gain,
+            set_replay_gain, scan_loudness, get_loudness, get_library, search_library,
+            get_library_track, start_library_scan, cancel_library_scan, get_cover_cache_stats,
+            set_cover_cache_budget, get_cover_rules, set_cover_rules, edit_tags, get_queue,
+            set_queue, queue_add, queue_play_next, queue_remove, queue_move, queue_clear,
+            queue_next, queue_previous, set_repeat, set_shuffle, get_player_state,
+            set_playback_state_interval, get_resume_session, set_resume_session
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            // окна уже закрыты, плеер ещё жив
+            if let RunEvent::Exit = event {
+                save_session(app);
+            }
+        });
 }
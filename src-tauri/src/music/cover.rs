@@ -0,0 +1,501 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use image::codecs::jpeg::JpegEncoder;
+use lofty::{Accessor, PictureType, Tag};
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+
+use super::file_stamp;
+use super::folder_cover::CoverRules;
+
+/// Размеры миниатюр, которые отдаёт протокол обложек
+pub const THUMBNAIL_SIZES: [u32; 3] = [64, 256, 512];
+/// Сколько байт картинок держать в памяти, если не задано иначе
+pub const DEFAULT_COVER_BUDGET: usize = 32 * 1024 * 1024;
+/// Качество JPEG для миниатюр
+const THUMBNAIL_QUALITY: u8 = 85;
+/// Сколько папок альбомов помнить; давно не встречавшиеся забываются первыми
+const MAX_FOLDERS: usize = 4096;
+
+/// Обложка в дисковом кэше; одинаковые картинки имеют один `id`
+#[derive(Clone, Debug)]
+pub struct CoverArt {
+    /// Хеш содержимого, по нему обложку запрашивает интерфейс
+    pub id: String,
+}
+
+/// Картинка в памяти и момент последнего обращения к ней
+#[derive(Debug)]
+struct Entry {
+    data: Arc<[u8]>,
+    mime: &'static str,
+    used: u64,
+}
+
+/// Обложка-файл в папке альбома. Запоминается вместе со временем изменения
+/// папки, чтобы заметить новый или заменённый файл.
+#[derive(Debug)]
+struct FolderCover {
+    modified: Option<(u64, u64)>,
+    cover: Option<(PathBuf, (u64, u64), String)>,
+    used: u64,
+}
+
+/// Папка и альбом: правило `{album}` зависит от тега
+type FolderKey = (PathBuf, Option<String>);
+
+/// Статистика кэша обложек для диагностики
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CoverCacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub budget: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// Сколько папок альбомов уже проверено на обложку-файл
+    pub folders: usize,
+}
+
+/// Обложки в памяти с вытеснением давно не использованных сверх бюджета.
+/// Ключ — id содержимого (и размер миниатюры), поэтому все треки альбома
+/// с одинаковой картинкой делят одну запись.
+#[derive(Debug)]
+pub struct CoverCache {
+    entries: HashMap<String, Entry>,
+    /// Порядок обращений: счётчик → ключ, самые старые первыми
+    recent: BTreeMap<u64, String>,
+    folders: HashMap<FolderKey, FolderCover>,
+    /// Порядок обращений к папкам, как `recent` для картинок
+    recent_folders: BTreeMap<u64, FolderKey>,
+    rules: CoverRules,
+    directory: PathBuf,
+    budget: usize,
+    bytes: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl CoverCache {
+    pub fn global() -> &'static Mutex<CoverCache> {
+        static CACHE: OnceCell<Mutex<CoverCache>> = OnceCell::new();
+
+        CACHE.get_or_init(|| {
+            let c = CoverCache::new();
+            Mutex::new(c)
+        })
+    }
+
+    fn new() -> Self {
+        let directory = dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("musa")
+            .join("covers");
+        CoverCache {
+            entries: HashMap::new(),
+            recent: BTreeMap::new(),
+            folders: HashMap::new(),
+            recent_folders: BTreeMap::new(),
+            rules: CoverRules::default(),
+            directory,
+            budget: DEFAULT_COVER_BUDGET,
+            bytes: 0,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Папка дискового кэша; приложение задаёт её при запуске
+    pub fn set_directory(&mut self, directory: PathBuf) {
+        self.directory = directory;
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Меняет бюджет памяти и сразу вытесняет лишнее
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    pub fn rules(&self) -> &CoverRules {
+        &self.rules
+    }
+
+    /// Новые правила поиска; уже найденные в папках обложки забываются
+    pub fn set_rules(&mut self, rules: CoverRules) {
+        self.rules = rules;
+        self.folders.clear();
+        self.recent_folders.clear();
+    }
+
+    pub fn stats(&self) -> CoverCacheStats {
+        CoverCacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            budget: self.budget,
+            hits: self.hits,
+            misses: self.misses,
+            evictions: self.evictions,
+            folders: self.folders.len(),
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<(Arc<[u8]>, &'static str)> {
+        let Some(entry) = self.entries.get_mut(key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.clock += 1;
+        self.recent.remove(&entry.used);
+        entry.used = self.clock;
+        self.recent.insert(self.clock, key.to_string());
+        Some((entry.data.clone(), entry.mime))
+    }
+
+    fn insert(&mut self, key: &str, data: Arc<[u8]>, mime: &'static str) {
+        // картинка больше всего бюджета вытеснила бы всё остальное
+        if data.len() > self.budget {
+            return;
+        }
+        self.clock += 1;
+        let size = data.len();
+        let entry = Entry {
+            data,
+            mime,
+            used: self.clock,
+        };
+        if let Some(old) = self.entries.insert(key.to_string(), entry) {
+            self.recent.remove(&old.used);
+            self.bytes -= old.data.len();
+        }
+        self.recent.insert(self.clock, key.to_string());
+        self.bytes += size;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.budget {
+            let Some((_, key)) = self.recent.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.data.len();
+                self.evictions += 1;
+            }
+        }
+    }
+
+    fn touch_folder(&mut self, key: &FolderKey) {
+        let Some(folder) = self.folders.get_mut(key) else {
+            return;
+        };
+        self.clock += 1;
+        self.recent_folders.remove(&folder.used);
+        folder.used = self.clock;
+        self.recent_folders.insert(self.clock, key.clone());
+    }
+
+    fn insert_folder(
+        &mut self,
+        key: FolderKey,
+        modified: Option<(u64, u64)>,
+        cover: Option<(PathBuf, (u64, u64), String)>,
+    ) {
+        self.clock += 1;
+        self.recent_folders.insert(self.clock, key.clone());
+        let folder = FolderCover {
+            modified,
+            cover,
+            used: self.clock,
+        };
+        if let Some(old) = self.folders.insert(key, folder) {
+            self.recent_folders.remove(&old.used);
+        }
+        while self.folders.len() > MAX_FOLDERS {
+            let Some((_, key)) = self.recent_folders.pop_first() else {
+                break;
+            };
+            self.folders.remove(&key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recent.clear();
+        self.folders.clear();
+        self.recent_folders.clear();
+        self.bytes = 0;
+    }
+}
+
+/// Обложка из тегов трека или из файла в его папке. Хеширование, запись
+/// на диск и поиск в папке идут без блокировки кэша.
+pub fn cover_art(path: &Path, tag: &Tag) -> Option<CoverArt> {
+    if let Some(data) = embedded_cover(tag) {
+        let directory = lock().ok()?.directory.clone();
+        return Some(store_original(&directory, &data));
+    }
+    folder_cover(path.parent()?, tag.album())
+}
+
+/// Файл обложки ищется и хешируется один раз на папку альбома
+fn folder_cover(dir: &Path, album: Option<&str>) -> Option<CoverArt> {
+    let key = (dir.to_path_buf(), album.map(str::to_string));
+    let modified = file_stamp(dir);
+    let (known, rules, directory) = {
+        let cache = lock().ok()?;
+        let known = cache
+            .folders
+            .get(&key)
+            .map(|folder| (folder.modified, folder.cover.clone()));
+        (known, cache.rules.clone(), cache.directory.clone())
+    };
+    if let Some((known_modified, cover)) = known {
+        let fresh = known_modified == modified
+            && cover
+                .as_ref()
+                .is_none_or(|(file, stamp, _)| file_stamp(file) == Some(*stamp));
+        if fresh {
+            lock().ok()?.touch_folder(&key);
+            return cover.map(|(_, _, id)| CoverArt { id });
+        }
+    }
+
+    let found = rules.find(dir, album).and_then(|(file, data)| {
+        let stamp = file_stamp(&file)?;
+        Some((file, stamp, data))
+    });
+    let (cover, art) = match found {
+        Some((file, stamp, data)) => {
+            let art = store_original(&directory, &data);
+            (Some((file, stamp, art.id.clone())), Some(art))
+        }
+        None => (None, None),
+    };
+    let mut cache = lock().ok()?;
+    // пока искали, правила могли смениться: такой результат не запоминаем
+    if cache.rules == rules {
+        cache.insert_folder(key, modified, cover);
+    }
+    art
+}
+
+/// Кладёт оригинал в дисковый кэш, если такой картинки там ещё нет
+fn store_original(directory: &Path, data: &[u8]) -> CoverArt {
+    let id = content_id(data);
+    let original = original_path(directory, &id);
+    // одинаковая картинка уже лежит в кэше от другого трека
+    if !original.exists() {
+        if let Err(err) = write_atomic(&original, data) {
+            eprintln!("Failed to cache cover {}: {err}", original.display());
+        }
+    }
+    CoverArt { id }
+}
+
+fn original_path(directory: &Path, id: &str) -> PathBuf {
+    directory.join(format!("{id}.orig"))
+}
+
+fn embedded_cover(tag: &Tag) -> Option<Vec<u8>> {
+    if let Some(picture) = tag.get_picture_type(PictureType::CoverFront) {
+        return Some(picture.data().to_vec());
+    }
+    tag.pictures()
+        .iter()
+        .find(|picture| {
+            matches!(
+                picture.pic_type(),
+                PictureType::Other | PictureType::BandLogo
+            )
+        })
+        .map(|picture| picture.data().to_vec())
+}
+
+fn lock() -> io::Result<std::sync::MutexGuard<'static, CoverCache>> {
+    CoverCache::global()
+        .lock()
+        .map_err(|_| io::Error::other("cover cache poisoned"))
+}
+
+/// Отдаёт обложку по пути запроса `/<id>` или `/<id>/<размер>`: из памяти,
+/// а при промахе с диска. Кэш блокируется только на поиск и вставку.
+pub fn serve_cover(request: &str) -> io::Result<(Arc<[u8]>, &'static str)> {
+    let (id, size) = parse_request(request)?;
+    let key = match size {
+        Some(size) => format!("{id}/{size}"),
+        None => id.to_string(),
+    };
+
+    let directory = {
+        let mut cache = lock()?;
+        if let Some(hit) = cache.get(&key) {
+            return Ok(hit);
+        }
+        cache.directory.clone()
+    };
+    let (data, mime) = read_cover(&directory, id, size)?;
+    let data: Arc<[u8]> = data.into();
+    lock()?.insert(&key, data.clone(), mime);
+    Ok((data, mime))
+}
+
+fn parse_request(request: &str) -> io::Result<(&str, Option<u32>)> {
+    let not_found = || io::Error::new(io::ErrorKind::NotFound, "cover not found");
+    let mut parts = request.trim_matches('/').split('/');
+    let id = parts
+        .next()
+        .filter(|id| is_content_id(id))
+        .ok_or_else(not_found)?;
+    let size = match parts.next() {
+        Some(size) => Some(
+            size.parse::<u32>()
+                .ok()
+                .filter(|size| THUMBNAIL_SIZES.contains(size))
+                .ok_or_else(not_found)?,
+        ),
+        None => None,
+    };
+    Ok((id, size))
+}
+
+/// Оригинал или миниатюра с диска; миниатюры создаются при первом запросе
+fn read_cover(
+    directory: &Path,
+    id: &str,
+    size: Option<u32>,
+) -> io::Result<(Vec<u8>, &'static str)> {
+    let original = original_path(directory, id);
+    let Some(size) = size else {
+        let data = fs::read(&original)?;
+        let mime = sniff_mime(&data).unwrap_or("application/octet-stream");
+        return Ok((data, mime));
+    };
+
+    let thumbnail = directory.join(format!("{id}-{size}.jpg"));
+    if let Ok(data) = fs::read(&thumbnail) {
+        return Ok((data, "image/jpeg"));
+    }
+
+    let image = image::load_from_memory(&fs::read(&original)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut data = Vec::new();
+    image
+        .thumbnail(size, size)
+        .to_rgb8()
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut data, THUMBNAIL_QUALITY))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if let Err(err) = write_atomic(&thumbnail, &data) {
+        eprintln!("Failed to cache thumbnail {}: {err}", thumbnail.display());
+    }
+    Ok((data, "image/jpeg"))
+}
+
+/// Тип картинки по первым байтам
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'B', b'M', ..] => Some("image/bmp"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// FNV-1a по содержимому: стабилен между запусками и версиями Rust
+fn content_id(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{hash:016x}{:08x}", data.len())
+}
+
+fn is_content_id(id: &str) -> bool {
+    id.len() == 24 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Пишет через временный файл: параллельные потоки могут сохранять
+/// одну и ту же обложку, и недописанный файл никто не увидит
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = path.with_extension(format!("{}.tmp", NEXT.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&temp, data)?;
+    fs::rename(temp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifies_covers_by_content() {
+        let id = content_id(b"cover");
+        assert!(is_content_id(&id));
+        assert_eq!(id, content_id(b"cover"));
+        assert_ne!(id, content_id(b"other"));
+        assert!(!is_content_id("../../etc/passwd"));
+
+        assert_eq!(
+            sniff_mime(&[0x89, b'P', b'N', b'G', 0x0D]),
+            Some("image/png")
+        );
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_mime(b"text"), None);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = CoverCache::new();
+        cache.set_budget(10);
+        cache.insert("a", Arc::from(&[0u8; 4][..]), "image/png");
+        cache.insert("b", Arc::from(&[0u8; 4][..]), "image/png");
+        assert!(cache.get("a").is_some());
+        cache.insert("c", Arc::from(&[0u8; 4][..]), "image/png");
+
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("a").is_some());
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.bytes), (2, 8));
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 1, 1));
+
+        cache.set_budget(4);
+        assert_eq!(cache.stats().entries, 1);
+        assert!(cache.get("a").is_some());
+    }
+
+    #[test]
+    fn forgets_least_recently_seen_folders() {
+        let mut cache = CoverCache::new();
+        let key = |i: usize| (PathBuf::from(format!("/music/{i}")), None);
+        for i in 0..MAX_FOLDERS {
+            cache.insert_folder(key(i), None, None);
+        }
+        cache.touch_folder(&key(0));
+        cache.insert_folder(key(MAX_FOLDERS), None, None);
+
+        assert_eq!(cache.stats().folders, MAX_FOLDERS);
+        assert!(cache.folders.contains_key(&key(0)));
+        assert!(!cache.folders.contains_key(&key(1)));
+        assert_eq!(cache.recent_folders.len(), MAX_FOLDERS);
+    }
+}
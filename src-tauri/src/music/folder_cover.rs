@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::cover::sniff_mime;
+
+/// Подстановка названия альбома в шаблон имени
+const ALBUM_PLACEHOLDER: &str = "{album}";
+
+/// Правила поиска обложки-файла рядом с треками
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CoverRules {
+    /// Шаблоны имени файла без расширения в порядке приоритета, без учёта
+    /// регистра; `*` — любые символы, `{album}` — название альбома
+    pub names: Vec<String>,
+    /// Расширения картинок без учёта регистра
+    pub extensions: Vec<String>,
+    /// Подпапки, где тоже ищется обложка, после самой папки
+    pub subfolders: Vec<String>,
+    /// Если ни одно имя не подошло, взять самую большую картинку
+    pub largest_fallback: bool,
+}
+
+impl Default for CoverRules {
+    fn default() -> Self {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        CoverRules {
+            names: strings(&["cover", "folder", "front", "albumart*", ALBUM_PLACEHOLDER]),
+            extensions: strings(&["jpg", "jpeg", "png", "webp"]),
+            subfolders: strings(&["Scans", "Artwork"]),
+            largest_fallback: true,
+        }
+    }
+}
+
+impl CoverRules {
+    /// Находит обложку в папке `dir` и возвращает путь и содержимое.
+    /// Файлы, которые по содержимому не картинки, пропускаются.
+    pub fn find(&self, dir: &Path, album: Option<&str>) -> Option<(PathBuf, Vec<u8>)> {
+        let mut folders = vec![self.images_in(dir)];
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_lowercase();
+                let wanted = self.subfolders.iter().any(|s| s.to_lowercase() == name);
+                if wanted && entry.path().is_dir() {
+                    folders.push(self.images_in(&entry.path()));
+                }
+            }
+        }
+
+        let patterns: Vec<String> = self
+            .names
+            .iter()
+            .filter_map(|name| match album {
+                Some(album) => Some(name.replace(ALBUM_PLACEHOLDER, album)),
+                None if name.contains(ALBUM_PLACEHOLDER) => None,
+                None => Some(name.clone()),
+            })
+            .map(|name| name.to_lowercase())
+            .collect();
+
+        for images in &folders {
+            for pattern in &patterns {
+                let found = images
+                    .iter()
+                    .filter(|(_, stem, _)| matches_pattern(pattern, stem))
+                    .find_map(|(path, _, _)| read_image(path));
+                if found.is_some() {
+                    return found;
+                }
+            }
+        }
+
+        if !self.largest_fallback {
+            return None;
+        }
+        let mut images: Vec<_> = folders.into_iter().flatten().collect();
+        images.sort_by_key(|image| std::cmp::Reverse(image.2));
+        images
+            .into_iter()
+            .find_map(|(path, _, _)| read_image(&path))
+    }
+
+    /// Картинки папки по расширению: путь, имя без расширения в нижнем
+    /// регистре и размер, отсортированные по имени для стабильного выбора
+    fn images_in(&self, dir: &Path) -> Vec<(PathBuf, String, u64)> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return Vec::new();
+        };
+        let mut images: Vec<_> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                let extension = path.extension()?.to_string_lossy().to_lowercase();
+                if !self
+                    .extensions
+                    .iter()
+                    .any(|e| e.to_lowercase() == extension)
+                {
+                    return None;
+                }
+                let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+                let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+                Some((path, stem, metadata.len()))
+            })
+            .collect();
+        images.sort();
+        images
+    }
+}
+
+fn read_image(path: &Path) -> Option<(PathBuf, Vec<u8>)> {
+    let data = fs::read(path).ok()?;
+    sniff_mime(&data)?;
+    Some((path.to_path_buf(), data))
+}
+
+/// Сопоставление с шаблоном, где `*` — любая последовательность символов
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_pattern("cover", "cover"));
+        assert!(!matches_pattern("cover", "cover2"));
+        assert!(matches_pattern("albumart*", "albumart_{guid}_large"));
+        assert!(matches_pattern("albumart*", "albumart"));
+        assert!(matches_pattern("*front*", "01 front scan"));
+        assert!(matches_pattern("a*b*c", "abc"));
+        assert!(!matches_pattern("a*bc", "abc_"));
+    }
+}
@@ -0,0 +1,416 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR_STR};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use thiserror::Error;
+
+use super::{file_stamp, read_tags, walk_audio, Track, TrackLoudness};
+
+/// Версия схемы в `PRAGMA user_version`
+const SCHEMA_VERSION: i32 = 1;
+
+#[derive(Error, Debug)]
+pub enum LibraryError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("Invalid stored tags: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Mutex poisoned")]
+    MutexPoisoned,
+}
+
+/// Что изменилось в индексе после обновления
+#[derive(Clone, Default, Serialize)]
+pub struct LibraryChanges {
+    pub added: Vec<Track>,
+    pub updated: Vec<Track>,
+    pub removed: Vec<String>,
+}
+
+impl LibraryChanges {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Индекс библиотеки на диске: теги каждого файла вместе с размером
+/// и временем изменения, чтобы перечитывать только изменившиеся файлы
+pub struct Library {
+    conn: Connection,
+}
+
+impl Library {
+    pub fn open(path: &Path) -> Result<Self, LibraryError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS tracks (
+                    path TEXT PRIMARY KEY,
+                    size INTEGER NOT NULL,
+                    modified INTEGER NOT NULL,
+                    tags TEXT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS roots (path TEXT PRIMARY KEY);
+                CREATE TABLE IF NOT EXISTS loudness (
+                    path TEXT PRIMARY KEY,
+                    measurement TEXT NOT NULL
+                );
+                PRAGMA user_version = {SCHEMA_VERSION};"
+            ))?;
+        }
+        Ok(Library { conn })
+    }
+
+    /// Приводит индекс для файлов и папок `paths` в соответствие с диском:
+    /// теги читаются только у новых и изменившихся файлов, исчезнувшие удаляются
+    pub fn refresh(&mut self, paths: &[String]) -> Result<LibraryChanges, LibraryError> {
+        let known = self.stamps_under(paths)?;
+        let mut seen = HashSet::new();
+        let mut changed = Vec::new();
+
+        for file in walk_audio(paths) {
+            let path = file.display().to_string();
+            let Some(stamp) = file_stamp(&file) else {
+                continue;
+            };
+            if known.get(&path) != Some(&stamp) {
+                changed.push((read_tags(&file), stamp));
+            }
+            seen.insert(path);
+        }
+        self.store(&changed)?;
+
+        let removed: Vec<String> = known
+            .keys()
+            .filter(|path| !seen.contains(*path))
+            .cloned()
+            .collect();
+        self.remove(&removed)?;
+
+        let mut changes = LibraryChanges {
+            removed,
+            ..Default::default()
+        };
+        for (track, _) in changed {
+            if known.contains_key(&track.path) {
+                changes.updated.push(track);
+            } else {
+                changes.added.push(track);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Записывает прочитанные треки вместе с размером и временем изменения
+    pub fn store(&mut self, tracks: &[(Track, (u64, u64))]) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO tracks (path, size, modified, tags) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(path) DO UPDATE SET
+                    size = excluded.size, modified = excluded.modified, tags = excluded.tags",
+            )?;
+            for (track, (size, modified)) in tracks {
+                let tags = serde_json::to_string(&track.metadata)?;
+                upsert.execute(params![track.path, *size as i64, *modified as i64, tags])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn remove(&mut self, paths: &[String]) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut delete = tx.prepare("DELETE FROM tracks WHERE path = ?1")?;
+            for path in paths {
+                delete.execute([path])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Папки и файлы, которые пользователь добавлял в библиотеку
+    pub fn roots(&self) -> Result<Vec<String>, LibraryError> {
+        let mut statement = self.conn.prepare("SELECT path FROM roots ORDER BY path")?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    pub fn add_roots(&self, roots: &[String]) -> Result<(), LibraryError> {
+        let mut insert = self
+            .conn
+            .prepare("INSERT OR IGNORE INTO roots (path) VALUES (?1)")?;
+        for root in roots {
+            insert.execute([root])?;
+        }
+        Ok(())
+    }
+
+    /// Треки из индекса, лежащие в папках `roots`, без обращения к файлам
+    pub fn tracks_under(&self, roots: &[String]) -> Result<Vec<Track>, LibraryError> {
+        let mut tracks = Vec::new();
+        let mut statement = self.conn.prepare(
+            "SELECT path, tags FROM tracks
+             WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2 ORDER BY path",
+        )?;
+        for root in roots {
+            let rows = statement.query_map(params![root, folder_prefix(root)], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            for row in rows {
+                let (path, tags) = row?;
+                tracks.push(Track {
+                    path,
+                    metadata: serde_json::from_str(&tags)?,
+                });
+            }
+        }
+        Ok(tracks)
+    }
+
+    /// Поиск по названию, исполнителю и альбому во всём индексе
+    pub fn search(&self, query: &str) -> Result<Vec<Track>, LibraryError> {
+        let pattern = format!("%{}%", escape_like(query));
+        let mut statement = self.conn.prepare(
+            "SELECT path, tags FROM tracks
+             WHERE json_extract(tags, '$.title') LIKE ?1 ESCAPE '\\'
+                OR json_extract(tags, '$.artists') LIKE ?1 ESCAPE '\\'
+                OR json_extract(tags, '$.album') LIKE ?1 ESCAPE '\\'
+             ORDER BY path",
+        )?;
+        let rows = statement.query_map([pattern], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut tracks = Vec::new();
+        for row in rows {
+            let (path, tags) = row?;
+            tracks.push(Track {
+                path,
+                metadata: serde_json::from_str(&tags)?,
+            });
+        }
+        Ok(tracks)
+    }
+
+    pub fn track(&self, path: &str) -> Result<Option<Track>, LibraryError> {
+        let tags: Option<String> = self
+            .conn
+            .query_row("SELECT tags FROM tracks WHERE path = ?1", [path], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(match tags {
+            Some(tags) => Some(Track {
+                path: path.to_string(),
+                metadata: serde_json::from_str(&tags)?,
+            }),
+            None => None,
+        })
+    }
+
+    /// Размер и время изменения известных файлов в папках `roots`
+    pub fn stamps_under(
+        &self,
+        roots: &[String],
+    ) -> Result<HashMap<String, (u64, u64)>, LibraryError> {
+        let mut stamps = HashMap::new();
+        let mut statement = self.conn.prepare(
+            "SELECT path, size, modified FROM tracks
+             WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        )?;
+        for root in roots {
+            let rows = statement.query_map(params![root, folder_prefix(root)], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, i64>(2)? as u64,
+                ))
+            })?;
+            for row in rows {
+                let (path, size, modified) = row?;
+                stamps.insert(path, (size, modified));
+            }
+        }
+        Ok(stamps)
+    }
+
+    /// Громкость трека, если он измерен и с тех пор не менялся
+    pub fn loudness(&self, path: &str) -> Result<Option<TrackLoudness>, LibraryError> {
+        let measurement: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT measurement FROM loudness WHERE path = ?1",
+                [path],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(measurement) = measurement else {
+            return Ok(None);
+        };
+        let loudness: TrackLoudness = serde_json::from_str(&measurement)?;
+        let fresh = file_stamp(Path::new(path)) == Some((loudness.size, loudness.modified));
+        Ok(fresh.then_some(loudness))
+    }
+
+    pub fn store_loudness(
+        &mut self,
+        measured: &[(String, TrackLoudness)],
+    ) -> Result<(), LibraryError> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut upsert = tx.prepare(
+                "INSERT INTO loudness (path, measurement) VALUES (?1, ?2)
+                 ON CONFLICT(path) DO UPDATE SET measurement = excluded.measurement",
+            )?;
+            for (path, loudness) in measured {
+                upsert.execute(params![path, serde_json::to_string(loudness)?])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Группирует треки в альбомы по папке и тегу альбома из индекса.
+    /// Треки без тега альбома и не попавшие в индекс считаются отдельными.
+    pub fn group_albums(&self, paths: Vec<String>) -> Result<Vec<Vec<String>>, LibraryError> {
+        let mut albums: HashMap<(PathBuf, String), Vec<String>> = HashMap::new();
+        let mut singles = Vec::new();
+        let mut statement = self
+            .conn
+            .prepare("SELECT json_extract(tags, '$.album') FROM tracks WHERE path = ?1")?;
+
+        for path in paths {
+            let album: Option<String> = statement
+                .query_row([&path], |row| row.get(0))
+                .optional()?
+                .flatten();
+            match album {
+                Some(album) => {
+                    let dir = Path::new(&path)
+                        .parent()
+                        .map(Path::to_path_buf)
+                        .unwrap_or_default();
+                    albums.entry((dir, album)).or_default().push(path);
+                }
+                None => singles.push(vec![path]),
+            }
+        }
+        Ok(albums.into_values().chain(singles).collect())
+    }
+
+    /// Альбомы, в которых хоть один трек не измерен, изменился
+    /// или измерен без громкости альбома
+    pub fn stale_albums(&self, albums: Vec<Vec<String>>) -> Result<Vec<Vec<String>>, LibraryError> {
+        let mut stale = Vec::new();
+        for tracks in albums {
+            let mut measured = true;
+            for path in &tracks {
+                measured = self.loudness(path)?.is_some_and(|l| l.album.is_some());
+                if !measured {
+                    break;
+                }
+            }
+            if !measured {
+                stale.push(tracks);
+            }
+        }
+        Ok(stale)
+    }
+}
+
+/// Экранирует `%`, `_` и `\` для LIKE ... ESCAPE '\'
+fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Начало путей всех файлов внутри папки. Сравнивается через `substr`,
+/// а не LIKE: LIKE в SQLite не различает регистр латиницы.
+fn folder_prefix(root: &str) -> String {
+    let root = root.trim_end_matches(['/', '\\']);
+    format!("{root}{MAIN_SEPARATOR_STR}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folder_prefix_matches_case_and_wildcards_exactly() {
+        let library = Library::open(Path::new(":memory:")).unwrap();
+        let separator = MAIN_SEPARATOR_STR;
+        let tracks: Vec<String> = ["100%_hits", "100%_HITS", "100xyhits"]
+            .iter()
+            .map(|dir| format!("{separator}music{separator}{dir}{separator}a.mp3"))
+            .collect();
+        for path in &tracks {
+            library
+                .conn
+                .execute(
+                    "INSERT INTO tracks (path, size, modified, tags) VALUES (?1, 0, 0, '{}')",
+                    [path],
+                )
+                .unwrap();
+        }
+
+        let root = format!("{separator}music{separator}100%_hits{separator}");
+        let found: Vec<String> = library.stamps_under(&[root]).unwrap().into_keys().collect();
+        assert_eq!(found, [tracks[0].clone()]);
+    }
+
+    #[test]
+    fn groups_albums_by_folder_and_indexed_tag() {
+        let library = Library::open(Path::new(":memory:")).unwrap();
+        let separator = MAIN_SEPARATOR_STR;
+        let path = |dir: &str, file: &str| format!("{separator}{dir}{separator}{file}");
+        for (track, tags) in [
+            (path("a", "1.mp3"), r#"{"album":"One"}"#),
+            (path("a", "2.mp3"), r#"{"album":"One"}"#),
+            (path("b", "1.mp3"), r#"{"album":"One"}"#),
+            (path("a", "3.mp3"), r#"{"album":null}"#),
+        ] {
+            library
+                .conn
+                .execute(
+                    "INSERT INTO tracks (path, size, modified, tags) VALUES (?1, 0, 0, ?2)",
+                    [&track, tags],
+                )
+                .unwrap();
+        }
+
+        let paths = ["1.mp3", "2.mp3", "3.mp3", "4.mp3"]
+            .iter()
+            .map(|file| path("a", file))
+            .chain([path("b", "1.mp3")])
+            .collect();
+        let mut albums = library.group_albums(paths).unwrap();
+        albums.sort();
+        assert_eq!(
+            albums,
+            [
+                vec![path("a", "1.mp3"), path("a", "2.mp3")],
+                vec![path("a", "3.mp3")],
+                vec![path("a", "4.mp3")],
+                vec![path("b", "1.mp3")],
+            ]
+        );
+    }
+}
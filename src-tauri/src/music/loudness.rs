@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lofty::{ItemKey, ItemValue, TagItem};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::file_stamp;
+use super::tag_editor::{primary_tag, save_atomic, TagEditError};
+use crate::player::{analyze_file, Loudness, LoudnessMeter};
+
+/// Измерение по EBU R128 в виде, пригодном для кэша и интерфейса
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Measurement {
+    /// Интегральная громкость, LUFS
+    pub integrated: f64,
+    /// Диапазон громкости, LU
+    pub range: f64,
+    /// Истинный пик, доля полной шкалы
+    pub true_peak: f32,
+}
+
+impl From<Measurement> for Loudness {
+    fn from(measurement: Measurement) -> Self {
+        Loudness {
+            integrated: measurement.integrated,
+            range: measurement.range,
+            true_peak: measurement.true_peak,
+        }
+    }
+}
+
+impl From<Loudness> for Measurement {
+    fn from(loudness: Loudness) -> Self {
+        Measurement {
+            integrated: loudness.integrated,
+            range: loudness.range,
+            true_peak: loudness.true_peak,
+        }
+    }
+}
+
+/// Громкость трека и альбома, к которому он относится
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackLoudness {
+    pub track: Measurement,
+    pub album: Option<Measurement>,
+    /// Размер и время изменения файла на момент измерения
+    pub size: u64,
+    pub modified: u64,
+}
+
+impl TrackLoudness {
+    /// Громкость одного трека, без альбома, с текущей отметкой файла
+    pub fn measured(path: &str, loudness: Loudness) -> Option<Self> {
+        let (size, modified) = file_stamp(Path::new(path))?;
+        Some(TrackLoudness {
+            track: loudness.into(),
+            album: None,
+            size,
+            modified,
+        })
+    }
+}
+
+/// Ход сканирования для интерфейса
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanProgress {
+    pub done: usize,
+    pub total: usize,
+    pub path: String,
+    pub error: Option<String>,
+}
+
+/// Кэш измерений прежних версий: путь → громкость
+#[derive(Default, Deserialize)]
+struct LegacyCache {
+    entries: HashMap<String, TrackLoudness>,
+}
+
+/// Читает измерения из прежнего `loudness.json`; теперь громкость
+/// хранится в индексе библиотеки
+pub fn read_legacy_cache(path: &Path) -> Vec<(String, TrackLoudness)> {
+    fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<LegacyCache>(&data).ok())
+        .map(|cache| cache.entries.into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// Измеряет громкость треков и альбомов параллельно. Альбомы делятся
+/// между потоками целиком: для громкости альбома хватает одного измерителя.
+pub fn scan_loudness(
+    albums: Vec<Vec<String>>,
+    progress: impl Fn(ScanProgress) + Sync,
+) -> Vec<(String, TrackLoudness)> {
+    let total = albums.iter().map(Vec::len).sum();
+    let done = AtomicUsize::new(0);
+
+    albums
+        .into_par_iter()
+        .flat_map_iter(|tracks| {
+            let mut album: Option<LoudnessMeter> = None;
+            let mut measured = Vec::new();
+
+            for path in tracks {
+                let result = analyze_file(&path);
+                let error = result.as_ref().err().map(|e| e.to_string());
+                if let Ok(meter) = result {
+                    if let Some(loudness) = meter
+                        .loudness()
+                        .and_then(|loudness| TrackLoudness::measured(&path, loudness))
+                    {
+                        measured.push((path.clone(), loudness));
+                    }
+                    match &mut album {
+                        Some(album) => album.merge(&meter),
+                        None => album = Some(meter),
+                    }
+                }
+
+                progress(ScanProgress {
+                    done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                    total,
+                    path,
+                    error,
+                });
+            }
+
+            let album = album
+                .and_then(|meter| meter.loudness())
+                .map(Measurement::from);
+            for (_, entry) in &mut measured {
+                entry.album = album;
+            }
+            measured
+        })
+        .collect()
+}
+
+/// Записывает ReplayGain в основной тег файла, создавая тег при
+/// необходимости, и обновляет отметку изменения, чтобы запись в кэше
+/// не устарела. Стандартные ключи lofty сам переводит в поле формата:
+/// TXXX в ID3v2, REPLAYGAIN_* в Vorbis comments.
+pub fn write_replay_gain(path: &str, loudness: &mut TrackLoudness) -> Result<(), TagEditError> {
+    let mut tag = primary_tag(Path::new(path))?;
+
+    let mut fields = vec![
+        (
+            ItemKey::ReplayGainTrackGain,
+            format!("{:.2} dB", Loudness::from(loudness.track).gain()),
+        ),
+        (
+            ItemKey::ReplayGainTrackPeak,
+            format!("{:.6}", loudness.track.true_peak),
+        ),
+    ];
+    if let Some(album) = loudness.album {
+        fields.push((
+            ItemKey::ReplayGainAlbumGain,
+            format!("{:.2} dB", Loudness::from(album).gain()),
+        ));
+        fields.push((
+            ItemKey::ReplayGainAlbumPeak,
+            format!("{:.6}", album.true_peak),
+        ));
+    }
+    for (key, value) in fields {
+        tag.remove_key(&key);
+        tag.insert_item(TagItem::new(key, ItemValue::Text(value)));
+    }
+    save_atomic(&tag, Path::new(path))?;
+
+    if let Some((size, modified)) = file_stamp(Path::new(path)) {
+        loudness.size = size;
+        loudness.modified = modified;
+    }
+    Ok(())
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use lofty::{Accessor, FileType, ItemKey, ItemValue, Probe, Tag};
+use serde::{Deserialize, Serialize};
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+use super::cover::cover_art;
+use crate::player::parse_gain;
+
+/// Теги и свойства потока одного файла
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TrackMetadata {
+    /// Название; если тега нет — имя файла
+    pub title: String,
+    pub artists: Vec<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track_number: Option<u32>,
+    pub track_total: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub year: Option<i32>,
+    /// Полная дата, если в теге больше, чем год
+    pub date: Option<String>,
+    pub genres: Vec<String>,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    /// Усиление ReplayGain, дБ
+    pub replay_gain_track: Option<f32>,
+    pub replay_gain_album: Option<f32>,
+    pub duration_ms: u64,
+    /// Средний битрейт, кбит/с
+    pub bitrate: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub codec: Option<String>,
+    pub bit_depth: Option<u32>,
+    /// Id обложки в дисковом кэше, отдаётся протоколом `cover://`
+    pub cover: Option<String>,
+    /// Остальные текстовые теги как есть
+    pub extras: BTreeMap<String, String>,
+}
+
+impl TrackMetadata {
+    pub fn read(path: &Path) -> Self {
+        let mut metadata = TrackMetadata {
+            title: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        // кодек однозначен по типу файла только у сжатых с потерями,
+        // у них же нет разрядности
+        let mut lossy = false;
+        if let Ok(tagged) = Probe::open(path).and_then(|p| p.read(true)) {
+            if let Some(tag) = tagged.primary_tag() {
+                metadata.apply_tag(tag);
+
+                // без кэша обложек трек читается и без обложки
+                metadata.cover = cover_art(path, tag).map(|cover| cover.id);
+            }
+
+            let properties = tagged.properties();
+            metadata.duration_ms = properties.duration().as_millis() as u64;
+            metadata.bitrate = properties
+                .audio_bitrate()
+                .or_else(|| properties.overall_bitrate());
+            metadata.sample_rate = properties.sample_rate();
+            metadata.channels = properties.channels();
+            metadata.codec = Some(format!("{:?}", tagged.file_type()).to_lowercase());
+            lossy = matches!(
+                tagged.file_type(),
+                FileType::MP3 | FileType::Opus | FileType::Vorbis | FileType::Speex
+            );
+        }
+
+        // lofty не различает кодеки внутри MP4 и не знает разрядность:
+        // заголовки потока читаются только там, где без них не обойтись
+        if !lossy {
+            if let Some((codec, bit_depth)) = stream_info(path) {
+                metadata.codec = Some(codec);
+                metadata.bit_depth = bit_depth;
+            }
+        }
+
+        metadata
+    }
+
+    fn apply_tag(&mut self, tag: &Tag) {
+        if let Some(title) = tag.title() {
+            self.title = title.to_string();
+        }
+
+        for item in tag.items() {
+            let value = match item.value() {
+                ItemValue::Text(value) | ItemValue::Locator(value) => value.trim(),
+                ItemValue::Binary(_) => continue,
+            };
+            if value.is_empty() {
+                continue;
+            }
+
+            match item.key() {
+                ItemKey::TrackTitle => {}
+                ItemKey::TrackArtist => self.artists.extend(split_values(value)),
+                ItemKey::AlbumArtist => self.album_artist = Some(value.to_string()),
+                ItemKey::AlbumTitle => self.album = Some(value.to_string()),
+                ItemKey::Genre => self.genres.extend(split_values(value)),
+                ItemKey::Composer => self.composer = Some(value.to_string()),
+                ItemKey::Comment => self.comment = Some(value.to_string()),
+                ItemKey::Year => {
+                    self.year = parse_year(value);
+                    self.date = (value.len() > 4).then(|| value.to_string());
+                }
+                ItemKey::TrackNumber => {
+                    let (number, total) = parse_pair(value);
+                    self.track_number = number;
+                    self.track_total = self.track_total.or(total);
+                }
+                ItemKey::TrackTotal => self.track_total = value.parse().ok(),
+                ItemKey::DiscNumber => {
+                    let (number, total) = parse_pair(value);
+                    self.disc_number = number;
+                    self.disc_total = self.disc_total.or(total);
+                }
+                ItemKey::DiscTotal => self.disc_total = value.parse().ok(),
+                ItemKey::ReplayGainTrackGain => self.replay_gain_track = parse_gain(value),
+                ItemKey::ReplayGainAlbumGain => self.replay_gain_album = parse_gain(value),
+                // в форматах без стандартного ключа ReplayGain приходит как есть
+                ItemKey::Unknown(key) if key.eq_ignore_ascii_case("REPLAYGAIN_TRACK_GAIN") => {
+                    self.replay_gain_track = parse_gain(value)
+                }
+                ItemKey::Unknown(key) if key.eq_ignore_ascii_case("REPLAYGAIN_ALBUM_GAIN") => {
+                    self.replay_gain_album = parse_gain(value)
+                }
+                ItemKey::Unknown(key) => {
+                    self.extras.insert(key.clone(), value.to_string());
+                }
+                key => {
+                    self.extras.insert(format!("{key:?}"), value.to_string());
+                }
+            }
+        }
+
+        dedup(&mut self.artists);
+        dedup(&mut self.genres);
+    }
+}
+
+/// Кодек и разрядность по заголовкам потока
+fn stream_info(path: &Path) -> Option<(String, Option<u32>)> {
+    let file = File::open(path).ok()?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            mss,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .ok()?;
+    let params = &probed.format.default_track()?.codec_params;
+    let codec = symphonia::default::get_codecs().get_codec(params.codec)?;
+    Some((codec.short_name.to_string(), params.bits_per_sample))
+}
+
+/// Несколько значений в одном теге: `A; B` или через нулевой символ (ID3v2.4)
+fn split_values(value: &str) -> impl Iterator<Item = String> + '_ {
+    value
+        .split([';', '\0'])
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::to_string)
+}
+
+fn dedup(values: &mut Vec<String>) {
+    let mut seen = Vec::with_capacity(values.len());
+    values.retain(|value| {
+        let fresh = !seen.contains(value);
+        if fresh {
+            seen.push(value.clone());
+        }
+        fresh
+    });
+}
+
+/// Номер вида `3` или `3/12`
+fn parse_pair(value: &str) -> (Option<u32>, Option<u32>) {
+    let mut parts = value.splitn(2, '/');
+    let number = parts.next().and_then(|n| n.trim().parse().ok());
+    let total = parts.next().and_then(|n| n.trim().parse().ok());
+    (number, total)
+}
+
+/// Год из `2001`, `2001-05-12` или `2001-05-12T00:00:00`
+fn parse_year(value: &str) -> Option<i32> {
+    let digits: String = value.chars().take_while(char::is_ascii_digit).collect();
+    (digits.len() == 4).then(|| digits.parse().ok()).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_numbers_and_lists() {
+        assert_eq!(parse_pair("3/12"), (Some(3), Some(12)));
+        assert_eq!(parse_pair(" 7 "), (Some(7), None));
+        assert_eq!(parse_year("2001-05-12"), Some(2001));
+        assert_eq!(parse_year("May 2001"), None);
+
+        let mut artists: Vec<String> = split_values("A; B\0A").collect();
+        dedup(&mut artists);
+        assert_eq!(artists, ["A", "B"]);
+    }
+}
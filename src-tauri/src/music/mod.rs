@@ -1,9 +1,17 @@
+mod cover;
+mod folder_cover;
+mod library;
+mod loudness;
+mod metadata;
+mod scan;
+mod tag_editor;
+mod watcher;
+
 use clap::{Parser, ValueEnum};
 use lofty::{read_from_path, AudioFile};
-use lofty::{Accessor, Probe};
 use rayon::prelude::*;
 use serde::Serialize;
-use serde_json::{json, Value};
+use std::cmp;
 use std::io::SeekFrom;
 use std::io::{Read, Seek};
 use std::{
@@ -13,7 +21,6 @@ use std::{
     io::BufReader,
     path::{Path, PathBuf},
 };
-use std::cmp;
 use symphonia::core::audio::SampleBuffer;
 use symphonia::core::formats::FormatOptions;
 use symphonia::core::io::MediaSourceStream;
@@ -27,6 +34,17 @@ use symphonia::default::get_codecs;
 use symphonia::default::get_probe;
 use walkdir::WalkDir;
 
+pub use cover::{serve_cover, CoverArt, CoverCache, CoverCacheStats};
+pub use folder_cover::CoverRules;
+pub use library::{Library, LibraryChanges, LibraryError};
+pub use loudness::{
+    read_legacy_cache, scan_loudness, write_replay_gain, ScanProgress, TrackLoudness,
+};
+pub use metadata::TrackMetadata;
+pub use scan::{scan_library, ScanBatch, ScanJobs, ScanSummary};
+pub use tag_editor::{edit_tracks, FieldEdit, TagEdit, TagEditError, TagEditSummary};
+pub use watcher::LibraryWatcher;
+
 use symphonia::core::errors::Error;
 use symphonia::core::formats::{SeekMode, SeekTo};
 use symphonia::core::units::Time;
@@ -61,10 +79,10 @@ pub struct Args {
 }
 
 /// Трек
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct Track {
     pub path: String,
-    pub tags: Value, // JSON объект со всеми тегами
+    pub metadata: TrackMetadata,
 }
 
 fn is_audio(path: &Path, allowed: &HashSet<&'static str>) -> bool {
@@ -74,117 +92,53 @@ fn is_audio(path: &Path, allowed: &HashSet<&'static str>) -> bool {
         .unwrap_or(false)
 }
 
-/// Чтение всех тегов
-fn read_tags(path: &Path) -> Track {
-    let mut obj = serde_json::Map::new();
-
-    if let Ok(tagged) = Probe::open(path).and_then(|p| p.read(true)) {
-        // Стандартные поля через Accessor
-        if let Some(tag) = tagged.primary_tag() {
-            let title = path.file_name().unwrap().to_string_lossy().to_string();
-            obj.insert("title".to_string(), json!(tag.title().unwrap_or(title.as_str())));
-            obj.insert("artist".to_string(), json!(tag.artist()));
-            obj.insert("album".to_string(), json!(tag.album()));
-            obj.insert("genre".to_string(), json!(tag.genre()));
-            if let Some(y) = tag.get_string(&lofty::ItemKey::Year) {
-                obj.insert("year".to_string(), json!(y));
-            }
-            if let Some(y) = tag.get_string(&lofty::ItemKey::TrackNumber) {
-                obj.insert("track_number".to_string(), json!(y));
-            }
-            if let Some(y) = tag.get_string(&lofty::ItemKey::TrackTotal) {
-                obj.insert("total_tracks".to_string(), json!(y));
-            }
-            if let Some(y) = tag.get_string(&lofty::ItemKey::DiscNumber) {
-                obj.insert("disc_number".to_string(), json!(y));
-            }
-            if let Some(y) = tag.get_string(&lofty::ItemKey::DiscTotal) {
-                obj.insert("total_discs".to_string(), json!(y));
-            }
-            if let Some(y) = tag.get_string(&lofty::ItemKey::Composer) {
-                obj.insert("composer".to_string(), json!(y));
-            }
-            if let Some(y) = tag.get_string(&lofty::ItemKey::Comment) {
-                obj.insert("comment".to_string(), json!(y));
-            }
-
-            let mut cache = CoverCache::global().lock().unwrap();
-
-            if let Some((cover, _)) = cache.cover_art(path, tag) {
-                obj.insert("cover".to_string(), json!(cover.as_base64()));
-            }
-
-            // Любые нестандартные поля
-            for item in tag.items() {
-                let key = format!("{:?}", item.key());
-                let val = match item.value() {
-                    lofty::ItemValue::Text(s) => json!(s),
-                    lofty::ItemValue::Locator(s) => json!(s),
-                    lofty::ItemValue::Binary(_) => json!("<binary>"),
-                };
-                obj.insert(key, val);
-            }
-
-            // obj.insert("bitrates".to_string(), json!(extract_levels(&path.display().to_string(), 80)));
-        }
-
-        // Duration
-        obj.insert(
-            "duration_ms".to_string(),
-            json!(tagged.properties().duration().as_millis() as u64),
-        );
-    }
-
+/// Чтение тегов и свойств потока
+pub fn read_tags(path: &Path) -> Track {
     Track {
         path: path.display().to_string(),
-        tags: Value::Object(obj),
+        metadata: TrackMetadata::read(path),
     }
 }
 
-/// Получить список треков
-pub fn get_music(paths: Vec<String>) -> Vec<Track> {
+/// Лениво обходит папки и отдельные файлы, выдавая аудиофайлы
+pub fn walk_audio(paths: &[String]) -> impl Iterator<Item = PathBuf> + Send + '_ {
     let exts: HashSet<&'static str> = [
         "mp3", "flac", "wav", "ogg", "m4a", "aac", "wma", "opus", "aiff", "alac", "mpc",
     ]
     .into_iter()
     .collect();
 
-    let mut tracks: Vec<Track> = Vec::new();
-
-    for root_str in paths {
-        let root = PathBuf::from(&root_str);
-
-        if !root.exists() {
-            eprintln!("Пропускаю отсутствующий путь: {}", root.display());
-            continue;
-        }
-        if root.is_dir() {
-            for entry in WalkDir::new(&root)
+    paths
+        .iter()
+        .filter(|root| {
+            let exists = Path::new(root).exists();
+            if !exists {
+                eprintln!("Пропускаю отсутствующий путь: {root}");
+            }
+            exists
+        })
+        // для файла WalkDir выдаёт его самого
+        .flat_map(|root| {
+            WalkDir::new(root)
                 .follow_links(false)
                 .into_iter()
                 .filter_map(Result::ok)
-                .filter(|e| e.file_type().is_file())
-            {
-                let path = entry.path();
-                if !is_audio(path, &exts) {
-                    continue;
-                }
-
-                tracks.push(read_tags(path));
-            }
-        } else {
-            if root.is_file() {
-                let path = root.as_path();
-                if !is_audio(path, &exts) {
-                    continue;
-                }
-
-                tracks.push(read_tags(path));
-            }
-        }
-    }
+        })
+        .filter(|e| e.file_type().is_file())
+        .filter(move |e| is_audio(e.path(), &exts))
+        .map(|e| e.into_path())
+}
 
-    tracks
+/// Размер и время изменения файла в секундах Unix
+pub fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((metadata.len(), modified))
 }
 
 pub fn play(path: String) -> bool {
@@ -445,7 +399,6 @@ pub async fn extract_waveform<P: AsRef<Path> + Send + 'static>(
     .await?
 }
 
-
 pub async fn extract_waveform_streaming<P: AsRef<Path> + Send + 'static>(
     path: P,
     points: usize,
@@ -478,10 +431,7 @@ pub async fn extract_waveform_streaming<P: AsRef<Path> + Send + 'static>(
 
         let track_id = track.id;
 
-        let sample_rate = track
-            .codec_params
-            .sample_rate
-            .ok_or("no sample rate")?;
+        let sample_rate = track.codec_params.sample_rate.ok_or("no sample rate")?;
 
         let total_frames = track
             .codec_params
@@ -525,7 +475,7 @@ pub async fn extract_waveform_streaming<P: AsRef<Path> + Send + 'static>(
             let mut local_max = 0.0f32;
             let mut frames_read = 0u64;
 
-            let max_frames_to_read = sample_rate as u64 / 25; 
+            let max_frames_to_read = sample_rate as u64 / 25;
 
             while frames_read < max_frames_to_read {
                 let packet = match format.next_packet() {
@@ -722,115 +672,3 @@ pub fn read_audio_samples(path: &str, points: usize) -> Vec<f32> {
 
     reduced
 }
-
-use std::collections::HashMap;
-use std::sync::Mutex;
-
-use base64::{engine::general_purpose, Engine as _};
-use lofty::{self, PictureType, Tag};
-use once_cell::sync::OnceCell;
-
-#[derive(Clone, Debug)]
-pub struct CoverArt {
-    pub base64: String,
-    pub cache: Option<PathBuf>,
-}
-
-impl CoverArt {
-    pub fn as_base64(&self) -> &str {
-        &self.base64
-    }
-}
-
-#[derive(Debug)]
-pub struct CoverCache {
-    entries: HashMap<String, CoverArt>,
-}
-
-impl CoverCache {
-    pub fn global() -> &'static Mutex<CoverCache> {
-        static CACHE: OnceCell<Mutex<CoverCache>> = OnceCell::new();
-
-        CACHE.get_or_init(|| {
-            let c = CoverCache::new();
-            Mutex::new(c)
-        })
-    }
-
-    fn new() -> Self {
-        CoverCache {
-            entries: HashMap::new(),
-        }
-    }
-
-    fn add_entry(&mut self, uuid: &str, cover: CoverArt) -> &CoverArt {
-        self.entries.entry(uuid.to_string()).or_insert(cover)
-    }
-
-    fn lookup(&self, uuid: &str) -> Option<&CoverArt> {
-        self.entries.get(uuid)
-    }
-
-    fn load_cover_art(&self, tag: &Tag, path: Option<&Path>) -> Option<(Vec<u8>, String)> {
-        // 1) ищем в тэгах
-        if let Some(picture) = tag.get_picture_type(PictureType::CoverFront) {
-            return Some((picture.data().to_vec(), picture.mime_type().to_string()));
-        } else {
-            for picture in tag.pictures() {
-                match picture.pic_type() {
-                    PictureType::Other | PictureType::BandLogo => {
-                        return Some((picture.data().to_vec(), picture.mime_type().to_string()))
-                    }
-                    _ => (),
-                }
-            }
-        }
-
-        // 2) ищем рядом с файлом
-        if let Some(p) = path {
-            let ext_covers = ["Cover.jpg", "Cover.png", "cover.jpg", "cover.png"];
-            for name in ext_covers {
-                let mut cover_file = PathBuf::from(p);
-                cover_file.push(name);
-                if let Ok(data) = std::fs::read(&cover_file) {
-                    // определяем MIME по расширению
-                    let mime = match cover_file.extension().and_then(|e| e.to_str()) {
-                        Some("png") => "image/png",
-                        Some("jpg") | Some("jpeg") => "image/jpeg",
-                        _ => "application/octet-stream",
-                    };
-                    return Some((data, mime.to_string()));
-                }
-            }
-        }
-
-        None
-    }
-
-    pub fn cover_art(&mut self, path: &Path, tag: &Tag) -> Option<(CoverArt, String)> {
-        // UUID упростим: просто имя файла
-        let uuid = path.to_string_lossy().to_string();
-
-        if let Some(c) = self.lookup(&uuid) {
-            return Some((c.clone(), uuid));
-        }
-
-        if let Some((data, mime)) = self.load_cover_art(tag, path.parent()) {
-            let encoded = general_purpose::STANDARD.encode(&data);
-            let base64_str = format!("data:{};base64,{}", mime, encoded);
-
-            let res = CoverArt {
-                base64: base64_str,
-                cache: None,
-            };
-            self.add_entry(&uuid, res.clone());
-            Some((res, uuid))
-        } else {
-            None
-        }
-    }
-
-    pub fn clear(&mut self) {
-        self.entries.clear();
-    }
-}
@@ -0,0 +1,188 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::{file_stamp, read_tags, walk_audio, Library, LibraryError, Track};
+
+/// Сколько прочитанных треков записывать и отдавать интерфейсу за раз
+const BATCH_SIZE: usize = 100;
+
+/// Очередная пачка прочитанных треков и счётчики
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanBatch {
+    /// Сколько аудиофайлов найдено к этому моменту
+    pub seen: usize,
+    /// Сколько из них уже обработано
+    pub done: usize,
+    /// Все найденные в пачке треки: новые и изменившиеся прочитаны
+    /// заново, остальные взяты из индекса
+    pub tracks: Vec<Track>,
+}
+
+/// Итог сканирования
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScanSummary {
+    pub seen: usize,
+    pub done: usize,
+    /// Треки, которых больше нет на диске; при отмене не удаляются
+    pub removed: Vec<String>,
+    pub cancelled: bool,
+}
+
+/// Текущее фоновое сканирование: новое отменяет предыдущее
+#[derive(Default)]
+pub struct ScanJobs {
+    current: Mutex<Option<Arc<AtomicBool>>>,
+}
+
+impl ScanJobs {
+    /// Отменяет идущее сканирование и выдаёт флаг отмены для нового
+    pub fn start(&self) -> Arc<AtomicBool> {
+        let cancel = Arc::new(AtomicBool::new(false));
+        if let Ok(mut current) = self.current.lock() {
+            if let Some(previous) = current.replace(cancel.clone()) {
+                previous.store(true, Ordering::Relaxed);
+            }
+        }
+        cancel
+    }
+
+    /// Возвращает `false`, если отменять нечего
+    pub fn cancel(&self) -> bool {
+        let Ok(mut current) = self.current.lock() else {
+            return false;
+        };
+        match current.take() {
+            Some(cancel) => {
+                cancel.store(true, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn finish(&self, cancel: &Arc<AtomicBool>) {
+        if let Ok(mut current) = self.current.lock() {
+            if current.as_ref().is_some_and(|c| Arc::ptr_eq(c, cancel)) {
+                *current = None;
+            }
+        }
+    }
+}
+
+fn lock(library: &Mutex<Library>) -> Result<MutexGuard<'_, Library>, LibraryError> {
+    library.lock().map_err(|_| LibraryError::MutexPoisoned)
+}
+
+/// Обходит папки и читает теги новых и изменившихся файлов на пуле rayon;
+/// пачки содержат и неизменные треки, так что по ним собирается вся библиотека.
+/// Индекс блокируется только на время записи пачки, поэтому запросы
+/// к библиотеке во время сканирования не ждут его окончания.
+pub fn scan_library(
+    library: &Mutex<Library>,
+    roots: &[String],
+    cancel: &AtomicBool,
+    on_batch: impl Fn(ScanBatch) + Sync,
+) -> Result<ScanSummary, LibraryError> {
+    let (known, indexed) = {
+        let library = lock(library)?;
+        let indexed: HashMap<String, Track> = library
+            .tracks_under(roots)?
+            .into_iter()
+            .map(|track| (track.path.clone(), track))
+            .collect();
+        (library.stamps_under(roots)?, Mutex::new(indexed))
+    };
+    let seen = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let pending = Mutex::new(Vec::new());
+
+    // у неизменных треков отметки нет: записывать их не нужно
+    let flush = |batch: Vec<(Track, Option<(u64, u64)>)>| -> Result<(), LibraryError> {
+        let mut changed = Vec::new();
+        let mut tracks = Vec::new();
+        for (track, stamp) in batch {
+            match stamp {
+                Some(stamp) => changed.push((track, stamp)),
+                None => tracks.push(track),
+            }
+        }
+        lock(library)?.store(&changed)?;
+        tracks.extend(changed.into_iter().map(|(track, _)| track));
+        on_batch(ScanBatch {
+            seen: seen.load(Ordering::Relaxed),
+            done: done.load(Ordering::Relaxed),
+            tracks,
+        });
+        Ok(())
+    };
+
+    let found: Vec<String> = walk_audio(roots)
+        .take_while(|_| !cancel.load(Ordering::Relaxed))
+        .inspect(|_| {
+            seen.fetch_add(1, Ordering::Relaxed);
+        })
+        .par_bridge()
+        .map(|file| -> Result<String, LibraryError> {
+            let path = file.display().to_string();
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(path);
+            }
+
+            let stamp = file_stamp(&file).filter(|stamp| known.get(&path) != Some(stamp));
+            let entry = match stamp {
+                Some(stamp) => Some((read_tags(&file), Some(stamp))),
+                None => indexed
+                    .lock()
+                    .map_err(|_| LibraryError::MutexPoisoned)?
+                    .remove(&path)
+                    .map(|track| (track, None)),
+            };
+            let batch = match entry {
+                Some(entry) => {
+                    let mut pending = pending.lock().map_err(|_| LibraryError::MutexPoisoned)?;
+                    pending.push(entry);
+                    (pending.len() >= BATCH_SIZE).then(|| std::mem::take(&mut *pending))
+                }
+                None => None,
+            };
+            done.fetch_add(1, Ordering::Relaxed);
+
+            if let Some(batch) = batch {
+                flush(batch)?;
+            }
+            Ok(path)
+        })
+        .collect::<Result<_, _>>()?;
+
+    let rest = pending
+        .into_inner()
+        .map_err(|_| LibraryError::MutexPoisoned)?;
+    if !rest.is_empty() {
+        flush(rest)?;
+    }
+
+    // при отмене обойдены не все файлы: удалять по неполному списку нельзя
+    let cancelled = cancel.load(Ordering::Relaxed);
+    let mut removed = Vec::new();
+    if !cancelled {
+        let found: HashSet<String> = found.into_iter().collect();
+        removed = known
+            .into_keys()
+            .filter(|path| !found.contains(path))
+            .collect();
+        lock(library)?.remove(&removed)?;
+    }
+
+    Ok(ScanSummary {
+        seen: seen.load(Ordering::Relaxed),
+        done: done.load(Ordering::Relaxed),
+        removed,
+        cancelled,
+    })
+}
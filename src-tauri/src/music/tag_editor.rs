@@ -0,0 +1,334 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lofty::{ItemKey, ItemValue, MimeType, Picture, PictureType, Probe, Tag, TagItem};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::cover::sniff_mime;
+use super::{file_stamp, read_tags, Library, LibraryError, Track};
+
+#[derive(Error, Debug)]
+pub enum TagEditError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Tag error: {0}")]
+    Tag(#[from] lofty::LoftyError),
+    #[error("Cover is not a supported image")]
+    InvalidCover,
+}
+
+/// Изменение одного поля: при пакетной правке `keep` оставляет
+/// у каждого файла его собственное значение
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FieldEdit<T> {
+    #[default]
+    Keep,
+    Clear,
+    Set(T),
+}
+
+/// Правка тегов для одного или нескольких треков; поля, которых нет
+/// в запросе, не меняются
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TagEdit {
+    pub title: FieldEdit<String>,
+    pub artists: FieldEdit<Vec<String>>,
+    pub album: FieldEdit<String>,
+    pub album_artist: FieldEdit<String>,
+    pub track_number: FieldEdit<u32>,
+    pub genres: FieldEdit<Vec<String>>,
+    pub year: FieldEdit<i32>,
+    pub comment: FieldEdit<String>,
+    /// Путь к картинке, которая станет встроенной обложкой
+    pub cover: FieldEdit<PathBuf>,
+}
+
+impl TagEdit {
+    fn apply(&self, tag: &mut Tag, cover: Option<&Picture>) {
+        set_text(tag, ItemKey::TrackTitle, &self.title, String::clone);
+        set_text(tag, ItemKey::TrackArtist, &self.artists, |v| v.join("; "));
+        set_text(tag, ItemKey::AlbumTitle, &self.album, String::clone);
+        set_text(tag, ItemKey::AlbumArtist, &self.album_artist, String::clone);
+        set_text(
+            tag,
+            ItemKey::TrackNumber,
+            &self.track_number,
+            u32::to_string,
+        );
+        set_text(tag, ItemKey::Genre, &self.genres, |v| v.join("; "));
+        set_text(tag, ItemKey::Year, &self.year, i32::to_string);
+        set_text(tag, ItemKey::Comment, &self.comment, String::clone);
+
+        if self.cover != FieldEdit::Keep {
+            // остальные картинки, в том числе `Other`, могут быть
+            // буклетом или фото исполнителя: их не трогаем
+            tag.remove_picture_type(PictureType::CoverFront);
+        }
+        if let Some(cover) = cover {
+            tag.push_picture(cover.clone());
+        }
+    }
+}
+
+fn set_text<T>(tag: &mut Tag, key: ItemKey, edit: &FieldEdit<T>, text: impl Fn(&T) -> String) {
+    match edit {
+        FieldEdit::Keep => {}
+        FieldEdit::Clear => tag.remove_key(&key),
+        FieldEdit::Set(value) => {
+            tag.remove_key(&key);
+            let value = text(value);
+            if !value.is_empty() {
+                // ключ, которого нет в формате тега, lofty пропускает
+                tag.insert_item(TagItem::new(key, ItemValue::Text(value)));
+            }
+        }
+    }
+}
+
+fn load_cover(path: &Path) -> Result<Picture, TagEditError> {
+    let data = fs::read(path)?;
+    let mime = sniff_mime(&data).ok_or(TagEditError::InvalidCover)?;
+    Ok(Picture::new_unchecked(
+        PictureType::CoverFront,
+        MimeType::from_str(mime),
+        None,
+        data,
+    ))
+}
+
+/// Основной тег файла (ID3v2, Vorbis comments, MP4, APE — какой
+/// у формата); если его нет, пустой тег этого типа
+pub(super) fn primary_tag(path: &Path) -> Result<Tag, TagEditError> {
+    let tagged = Probe::open(path)?.read(false)?;
+    let tag_type = tagged.primary_tag_type();
+    Ok(tagged
+        .primary_tag()
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tag_type)))
+}
+
+/// Записывает правку в основной тег файла, создавая тег при необходимости
+pub fn write_tags(path: &Path, edit: &TagEdit) -> Result<(), TagEditError> {
+    let cover = match &edit.cover {
+        FieldEdit::Set(cover) => Some(load_cover(cover)?),
+        _ => None,
+    };
+
+    let mut tag = primary_tag(path)?;
+    edit.apply(&mut tag, cover.as_ref());
+    save_atomic(&tag, path)
+}
+
+/// Теги пишутся в копию рядом с файлом, которая затем заменяет оригинал:
+/// при сбое посреди записи исходный файл остаётся целым
+pub(super) fn save_atomic(tag: &Tag, path: &Path) -> Result<(), TagEditError> {
+    let temp = edit_copy_path(path);
+    let result = replace_with_copy(tag, path, &temp);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Копия для правки лежит рядом с файлом. Формат lofty определяет
+/// по содержимому, а не по расширению, поэтому имя не похоже на аудиофайл
+/// и копию не подхватят сканер и наблюдатель за папками.
+fn edit_copy_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.musa-edit.tmp"))
+}
+
+/// Копия сбрасывается на диск до переименования, а после него — папка:
+/// иначе при сбое питания на месте файла может оказаться пустышка
+fn replace_with_copy(tag: &Tag, path: &Path, temp: &Path) -> Result<(), TagEditError> {
+    fs::copy(path, temp)?;
+    tag.save_to_path(temp)?;
+    File::open(temp)?.sync_all()?;
+    fs::rename(temp, path)?;
+    sync_parent(path)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => File::open(dir)?.sync_all(),
+        None => Ok(()),
+    }
+}
+
+/// Вне Unix папку не открыть как файл: на диск сбрасывается только копия
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Файл, который не удалось изменить
+#[derive(Serialize)]
+pub struct TagEditFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// Итог пакетной правки
+#[derive(Default, Serialize)]
+pub struct TagEditSummary {
+    /// Перечитанные после записи треки
+    pub updated: Vec<Track>,
+    pub failed: Vec<TagEditFailure>,
+}
+
+/// Применяет правку ко всем `paths` и обновляет их в индексе библиотеки.
+/// Ошибка в одном файле не останавливает остальные.
+pub fn edit_tracks(
+    library: &Mutex<Library>,
+    paths: &[String],
+    edit: &TagEdit,
+) -> Result<TagEditSummary, LibraryError> {
+    let mut summary = TagEditSummary::default();
+    let mut stored = Vec::new();
+
+    for path in paths {
+        let file = Path::new(path);
+        match write_tags(file, edit) {
+            Ok(()) => {
+                if let Some(stamp) = file_stamp(file) {
+                    stored.push((read_tags(file), stamp));
+                }
+            }
+            Err(err) => summary.failed.push(TagEditFailure {
+                path: path.clone(),
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    library
+        .lock()
+        .map_err(|_| LibraryError::MutexPoisoned)?
+        .store(&stored)?;
+    summary.updated = stored.into_iter().map(|(track, _)| track).collect();
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use lofty::{Accessor, TagType};
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("musa-tags-{}-{name}", std::process::id()))
+    }
+
+    /// MP3 из нескольких тихих кадров MPEG-1 Layer III, 128 кбит/с, 44.1 кГц
+    fn silent_mp3(name: &str) -> PathBuf {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+        let path = temp_path(name);
+        fs::write(&path, frame.repeat(8)).unwrap();
+        path
+    }
+
+    fn picture(kind: PictureType, data: &[u8]) -> Picture {
+        Picture::new_unchecked(kind, MimeType::Png, None, data.to_vec())
+    }
+
+    fn pictures(path: &Path) -> Vec<(PictureType, Vec<u8>)> {
+        primary_tag(path)
+            .unwrap()
+            .pictures()
+            .iter()
+            .map(|picture| (picture.pic_type(), picture.data().to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn missing_fields_are_kept() {
+        let edit: TagEdit = serde_json::from_str(
+            r#"{"title": {"set": "Song"}, "year": "clear", "genres": {"set": ["A", "B"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(edit.title, FieldEdit::Set("Song".to_string()));
+        assert_eq!(edit.year, FieldEdit::Clear);
+        assert_eq!(edit.artists, FieldEdit::Keep);
+        assert_eq!(edit.cover, FieldEdit::Keep);
+        assert_eq!(
+            edit.genres,
+            FieldEdit::Set(vec!["A".to_string(), "B".to_string()])
+        );
+    }
+
+    #[test]
+    fn edits_round_trip_through_the_file() {
+        let path = silent_mp3("round-trip.mp3");
+        let cover = temp_path("cover.png");
+        let png = b"\x89PNG\r\n\x1a\nfront".to_vec();
+        fs::write(&cover, &png).unwrap();
+
+        // картинка `Other` не обложка: правка обложки её не трогает
+        let mut tag = primary_tag(&path).unwrap();
+        tag.push_picture(picture(PictureType::Other, b"\x89PNG\r\n\x1a\nother"));
+        save_atomic(&tag, &path).unwrap();
+
+        let set = TagEdit {
+            title: FieldEdit::Set("Old".to_string()),
+            artists: FieldEdit::Set(vec!["Artist".to_string()]),
+            album: FieldEdit::Set("Album".to_string()),
+            cover: FieldEdit::Set(cover.clone()),
+            ..Default::default()
+        };
+        write_tags(&path, &set).unwrap();
+
+        let update = TagEdit {
+            title: FieldEdit::Set("New".to_string()),
+            album: FieldEdit::Clear,
+            ..Default::default()
+        };
+        write_tags(&path, &update).unwrap();
+
+        let tag = primary_tag(&path).unwrap();
+        assert_eq!(tag.title(), Some("New"));
+        assert_eq!(tag.artist(), Some("Artist"));
+        assert_eq!(tag.album(), None);
+        let kinds: Vec<PictureType> = pictures(&path).into_iter().map(|(kind, _)| kind).collect();
+        assert!(kinds.contains(&PictureType::Other));
+        assert!(pictures(&path).contains(&(PictureType::CoverFront, png)));
+
+        let clear = TagEdit {
+            cover: FieldEdit::Clear,
+            ..Default::default()
+        };
+        write_tags(&path, &clear).unwrap();
+        let kinds: Vec<PictureType> = pictures(&path).into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [PictureType::Other]);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(cover).unwrap();
+    }
+
+    #[test]
+    fn failed_save_keeps_original() {
+        // не аудио: lofty не сможет записать тег в копию
+        let path = temp_path("broken.mp3");
+        fs::write(&path, b"not an mp3 file").unwrap();
+
+        let mut tag = Tag::new(TagType::Id3v2);
+        tag.insert_item(TagItem::new(
+            ItemKey::TrackTitle,
+            ItemValue::Text("Title".to_string()),
+        ));
+        assert!(save_atomic(&tag, &path).is_err());
+
+        assert_eq!(fs::read(&path).unwrap(), b"not an mp3 file");
+        assert!(!edit_copy_path(&path).exists());
+        fs::remove_file(path).unwrap();
+    }
+}
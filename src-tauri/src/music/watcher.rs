@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::event::ModifyKind;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Сколько ждать тишины, прежде чем обработать пачку событий
+const DEBOUNCE: Duration = Duration::from_millis(500);
+/// Дольше этого не копим даже при непрерывном потоке событий
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Следит за папками библиотеки и отдаёт изменившиеся пути пачками
+pub struct LibraryWatcher {
+    watcher: RecommendedWatcher,
+    watched: HashSet<PathBuf>,
+}
+
+impl LibraryWatcher {
+    /// `on_change` вызывается в отдельном потоке с путями файлов и папок,
+    /// которые появились, изменились или исчезли
+    pub fn new(on_change: impl Fn(Vec<PathBuf>) + Send + 'static) -> notify::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            match event {
+                // метаданные и чтение на теги не влияют
+                Ok(event) => match event.kind {
+                    EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => {}
+                    _ => {
+                        let _ = sender.send(event.paths);
+                    }
+                },
+                Err(err) => eprintln!("Library watcher error: {err}"),
+            }
+        })?;
+
+        std::thread::Builder::new()
+            .name("musa-watcher".to_string())
+            .spawn(move || debounce(receiver, on_change))?;
+
+        Ok(LibraryWatcher {
+            watcher,
+            watched: HashSet::new(),
+        })
+    }
+
+    /// Начинает следить за путями, которых ещё нет среди отслеживаемых
+    pub fn watch(&mut self, roots: &[String]) {
+        for root in roots {
+            let path = PathBuf::from(root);
+            if self.watched.contains(&path) || !path.exists() {
+                continue;
+            }
+            match self.watcher.watch(&path, RecursiveMode::Recursive) {
+                Ok(()) => {
+                    self.watched.insert(path);
+                }
+                Err(err) => eprintln!("Failed to watch {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+/// Пути, накопленные с первого события пачки
+struct Batch {
+    paths: HashSet<PathBuf>,
+    started: Instant,
+    last: Instant,
+}
+
+impl Batch {
+    fn new(paths: Vec<PathBuf>, now: Instant) -> Self {
+        Batch {
+            paths: paths.into_iter().collect(),
+            started: now,
+            last: now,
+        }
+    }
+
+    fn add(&mut self, paths: Vec<PathBuf>, now: Instant) {
+        self.paths.extend(paths);
+        self.last = now;
+    }
+
+    /// Пачка отдаётся после `DEBOUNCE` тишины, но не позже `MAX_DELAY`
+    /// с первого события
+    fn deadline(&self) -> Instant {
+        (self.last + DEBOUNCE).min(self.started + MAX_DELAY)
+    }
+}
+
+/// Копит пути, пока события идут чаще `DEBOUNCE`, и отдаёт их разом
+fn debounce(receiver: Receiver<Vec<PathBuf>>, on_change: impl Fn(Vec<PathBuf>)) {
+    while let Ok(paths) = receiver.recv() {
+        let mut batch = Batch::new(paths, Instant::now());
+        let mut open = true;
+
+        loop {
+            let now = Instant::now();
+            if now >= batch.deadline() {
+                break;
+            }
+            match receiver.recv_timeout(batch.deadline() - now) {
+                Ok(paths) => batch.add(paths, Instant::now()),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+
+        on_change(batch.paths.into_iter().collect());
+        if !open {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_events_until_quiet() {
+        let start = Instant::now();
+        let mut batch = Batch::new(vec![PathBuf::from("a")], start);
+        batch.add(
+            vec![PathBuf::from("b"), PathBuf::from("a")],
+            start + Duration::from_millis(300),
+        );
+
+        assert_eq!(batch.deadline(), start + Duration::from_millis(800));
+        let expected: HashSet<PathBuf> = ["a", "b"].into_iter().map(PathBuf::from).collect();
+        assert_eq!(batch.paths, expected);
+    }
+
+    #[test]
+    fn caps_delay_under_steady_events() {
+        let start = Instant::now();
+        let mut batch = Batch::new(vec![PathBuf::from("a")], start);
+        let mut now = start;
+        while now < start + Duration::from_secs(6) {
+            now += Duration::from_millis(400);
+            batch.add(vec![PathBuf::from("a")], now);
+        }
+
+        assert_eq!(batch.deadline(), start + MAX_DELAY);
+        assert_eq!(batch.paths.len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use serde::Serialize;
+use symphonia::core::audio::Channels;
+
+/// Как каналы файла раскладываются по каналам устройства
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixMode {
+    Passthrough,
+    Upmix,
+    Downmix,
+}
+
+/// Описание выбранной раскладки для интерфейса
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelLayout {
+    pub source: String,
+    pub source_channels: usize,
+    pub output_channels: usize,
+    pub mode: MixMode,
+}
+
+/// Матрица смешивания каналов: `output × input` коэффициентов
+pub struct ChannelMap {
+    inputs: usize,
+    outputs: usize,
+    matrix: Vec<f32>,
+    pub layout: ChannelLayout,
+}
+
+impl ChannelMap {
+    pub fn new(channels: Channels, outputs: usize) -> Self {
+        let inputs = channels.count();
+        let mode = if inputs == outputs {
+            MixMode::Passthrough
+        } else if inputs < outputs {
+            MixMode::Upmix
+        } else {
+            MixMode::Downmix
+        };
+
+        let mut matrix = vec![0.0; inputs * outputs];
+        if mode == MixMode::Passthrough {
+            for ch in 0..inputs {
+                matrix[ch * inputs + ch] = 1.0;
+            }
+        } else {
+            let stereo = stereo_coefficients(channels);
+            for (input, (l, r)) in stereo.into_iter().enumerate() {
+                match outputs {
+                    1 => matrix[input] = (l + r) * 0.5,
+                    _ => {
+                        matrix[input] = l;
+                        matrix[inputs + input] = r;
+                    }
+                }
+            }
+        }
+
+        ChannelMap {
+            inputs,
+            outputs,
+            matrix,
+            layout: ChannelLayout {
+                source: layout_name(channels),
+                source_channels: inputs,
+                output_channels: outputs,
+                mode,
+            },
+        }
+    }
+
+    pub fn is_passthrough(&self) -> bool {
+        self.layout.mode == MixMode::Passthrough
+    }
+
+    /// Переводит чередующиеся сэмплы из раскладки файла в раскладку устройства
+    pub fn apply(&self, input: &[f32], out: &mut Vec<f32>) {
+        out.clear();
+        out.reserve(input.len() / self.inputs * self.outputs);
+
+        for frame in input.chunks_exact(self.inputs) {
+            for row in self.matrix.chunks_exact(self.inputs) {
+                out.push(frame.iter().zip(row).map(|(s, k)| s * k).sum());
+            }
+        }
+    }
+}
+
+/// Коэффициенты сведения в стерео (ITU-R BS.775): центр и тылы с −3 дБ,
+/// LFE отбрасывается. Каждая сторона нормируется, чтобы сумма не клиповала.
+fn stereo_coefficients(channels: Channels) -> Vec<(f32, f32)> {
+    if channels.count() == 1 {
+        return vec![(1.0, 1.0)];
+    }
+
+    let left_side = Channels::FRONT_LEFT_CENTRE
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH
+        | Channels::REAR_LEFT
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::SIDE_LEFT
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT;
+    let right_side = Channels::FRONT_RIGHT_CENTRE
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH
+        | Channels::REAR_RIGHT
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::SIDE_RIGHT
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT;
+
+    let mut coefficients: Vec<(f32, f32)> = channels
+        .iter()
+        .map(|ch| {
+            if ch == Channels::FRONT_LEFT {
+                (1.0, 0.0)
+            } else if ch == Channels::FRONT_RIGHT {
+                (0.0, 1.0)
+            } else if ch == Channels::LFE1 || ch == Channels::LFE2 {
+                (0.0, 0.0)
+            } else if left_side.contains(ch) {
+                (FRAC_1_SQRT_2, 0.0)
+            } else if right_side.contains(ch) {
+                (0.0, FRAC_1_SQRT_2)
+            } else {
+                // центральные каналы делятся поровну
+                (FRAC_1_SQRT_2, FRAC_1_SQRT_2)
+            }
+        })
+        .collect();
+
+    let left: f32 = coefficients.iter().map(|(l, _)| l).sum();
+    let right: f32 = coefficients.iter().map(|(_, r)| r).sum();
+    let norm = left.max(right).max(1.0);
+    for (l, r) in &mut coefficients {
+        *l /= norm;
+        *r /= norm;
+    }
+
+    coefficients
+}
+
+fn layout_name(channels: Channels) -> String {
+    let lfe = (channels & (Channels::LFE1 | Channels::LFE2)).count();
+    let count = channels.count();
+    match (count, lfe) {
+        (1, _) => "mono".to_string(),
+        (2, 0) => "stereo".to_string(),
+        (n, 0) => format!("{n}.0"),
+        (n, l) => format!("{}.{}", n - l, l),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upmixes_mono_to_both_channels() {
+        let map = ChannelMap::new(Channels::FRONT_LEFT, 2);
+        let mut out = Vec::new();
+
+        map.apply(&[0.5, -0.25], &mut out);
+
+        assert_eq!(out, vec![0.5, 0.5, -0.25, -0.25]);
+        assert_eq!(map.layout.mode, MixMode::Upmix);
+    }
+
+    #[test]
+    fn downmixes_surround_without_clipping() {
+        let surround = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let map = ChannelMap::new(surround, 2);
+        let mut out = Vec::new();
+
+        map.apply(&[1.0; 6], &mut out);
+
+        assert_eq!(out.len(), 2);
+        assert!(out.iter().all(|s| *s <= 1.0 && *s > 0.9));
+        assert_eq!(map.layout.source, "5.1");
+    }
+}
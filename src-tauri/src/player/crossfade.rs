@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+use std::f32::consts::FRAC_PI_2;
+
+use serde::{Deserialize, Serialize};
+
+use super::Source;
+
+/// Форма кривой перехода
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FadeCurve {
+    Linear,
+    #[default]
+    EqualPower,
+    SCurve,
+}
+
+impl FadeCurve {
+    /// Коэффициенты (уходящий, входящий) для `t` от 0 до 1
+    pub fn gains(self, t: f32) -> (f32, f32) {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            FadeCurve::Linear => (1.0 - t, t),
+            FadeCurve::EqualPower => ((t * FRAC_PI_2).cos(), (t * FRAC_PI_2).sin()),
+            FadeCurve::SCurve => {
+                let s = t * t * (3.0 - 2.0 * t);
+                (1.0 - s, s)
+            }
+        }
+    }
+}
+
+/// Настройки кроссфейда
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Crossfade {
+    /// Длительность при естественном окончании трека, секунды (0 — выключено)
+    pub duration: f32,
+    pub curve: FadeCurve,
+    /// Длительность при ручном переключении, секунды
+    pub skip_duration: f32,
+}
+
+impl Default for Crossfade {
+    fn default() -> Self {
+        Crossfade {
+            duration: 0.0,
+            curve: FadeCurve::default(),
+            skip_duration: 0.3,
+        }
+    }
+}
+
+impl Crossfade {
+    pub fn is_enabled(&self) -> bool {
+        self.duration > 0.0
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.duration >= 0.0 && self.skip_duration >= 0.0
+    }
+}
+
+/// Активный переход между двумя треками
+pub struct Fade {
+    pub incoming: Source,
+    /// Сэмплы входящего трека, ещё не смешанные с уходящим
+    pending: VecDeque<f32>,
+    curve: FadeCurve,
+    length: usize,   // во фреймах
+    position: usize, // во фреймах
+}
+
+impl Fade {
+    pub fn new(incoming: Source, curve: FadeCurve, seconds: f32) -> Self {
+        let length = (seconds * incoming.output_rate).max(1.0) as usize;
+        Fade {
+            incoming,
+            pending: VecDeque::new(),
+            curve,
+            length,
+            position: 0,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.position >= self.length
+    }
+
+    /// Смешивает декодированный фрагмент уходящего трека с входящим
+    /// и дописывает результат в `out`
+    pub fn mix(&mut self, outgoing: &[f32], channels: usize, out: &mut VecDeque<f32>) {
+        while self.pending.len() < outgoing.len() {
+            if !self.incoming.decode_next(&mut self.pending) {
+                break;
+            }
+        }
+
+        for frame in outgoing.chunks(channels) {
+            if self.is_complete() {
+                break;
+            }
+
+            let (g_out, g_in) = self.curve.gains(self.position as f32 / self.length as f32);
+            for &sample in frame {
+                let incoming = self.pending.pop_front().unwrap_or(0.0);
+                out.push_back(sample * g_out + incoming * g_in);
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Завершает переход: входящий трек становится текущим,
+    /// его недоигранные сэмплы уходят в `out`
+    pub fn finish(self, out: &mut VecDeque<f32>) -> Source {
+        out.extend(self.pending);
+        self.incoming
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::{OutputSpec, ResampleQuality};
+
+    const RATE: u32 = 8000;
+
+    /// Стерео WAV, 16 бит, с постоянным уровнем 0.5
+    fn constant_wav(name: &str, frames: usize) -> String {
+        let path =
+            std::env::temp_dir().join(format!("musa-fade-{}-{name}.wav", std::process::id()));
+        let data = (frames * 4) as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&RATE.to_le_bytes());
+        bytes.extend_from_slice(&(RATE * 4).to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data.to_le_bytes());
+        for _ in 0..frames * 2 {
+            bytes.extend_from_slice(&16384i16.to_le_bytes());
+        }
+        std::fs::write(&path, bytes).unwrap();
+        path.display().to_string()
+    }
+
+    fn linear_fade(path: &str, frames: usize) -> Fade {
+        let output = OutputSpec {
+            sample_rate: RATE,
+            channels: 2,
+        };
+        let incoming = Source::open(path, output, ResampleQuality::default()).unwrap();
+        Fade::new(incoming, FadeCurve::Linear, frames as f32 / RATE as f32)
+    }
+
+    #[test]
+    fn curves_keep_level_across_fade() {
+        for curve in [FadeCurve::Linear, FadeCurve::EqualPower, FadeCurve::SCurve] {
+            assert_eq!(curve.gains(0.0), (1.0, 0.0));
+            let (out, incoming) = curve.gains(1.0);
+            assert!(out.abs() < 1e-6 && (incoming - 1.0).abs() < 1e-6);
+            let (out, incoming) = curve.gains(0.5);
+            assert!((out - incoming).abs() < 1e-6);
+
+            for t in [0.0, 0.25, 0.5, 0.75, 1.0] {
+                let (out, incoming) = curve.gains(t);
+                let sum = match curve {
+                    FadeCurve::EqualPower => out * out + incoming * incoming,
+                    _ => out + incoming,
+                };
+                assert!((sum - 1.0).abs() < 1e-6, "{curve:?} at {t}: {sum}");
+            }
+        }
+    }
+
+    #[test]
+    fn short_incoming_track_fades_against_silence() {
+        let path = constant_wav("short", 100);
+        let mut fade = linear_fade(&path, 1000);
+        let mut out = VecDeque::new();
+        fade.mix(&[1.0; 800], 2, &mut out);
+
+        assert_eq!(out.len(), 800);
+        assert!((out[2 * 50] - (0.95 + 0.5 * 0.05)).abs() < 1e-4);
+        // входящий трек кончился: звучит только затухающий уходящий
+        assert!((out[2 * 200] - 0.8).abs() < 1e-4);
+        assert!(!fade.is_complete());
+
+        fade.finish(&mut out);
+        assert_eq!(out.len(), 800);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn finish_hands_over_unmixed_incoming_samples() {
+        let path = constant_wav("long", 1000);
+        let mut fade = linear_fade(&path, 100);
+        let mut out = VecDeque::new();
+        fade.mix(&[1.0; 120], 2, &mut out);
+        assert!(!fade.is_complete());
+        fade.mix(&[1.0; 120], 2, &mut out);
+
+        // второй фрагмент смешан только до конца перехода
+        assert!(fade.is_complete());
+        assert_eq!(out.len(), 200);
+        assert!((out[2 * 50] - 0.75).abs() < 1e-4);
+
+        fade.finish(&mut out);
+        assert_eq!(out.len(), 2000);
+        assert!(out.iter().skip(200).all(|&s| (s - 0.5).abs() < 1e-4));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+use cpal::traits::{DeviceTrait, HostTrait};
+use serde::{Deserialize, Serialize};
+
+use super::PlayerError;
+
+/// Выбранное устройство вывода. Пустые поля — устройство по умолчанию.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct OutputDevice {
+    pub host: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HostInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub devices: Vec<DeviceInfo>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceInfo {
+    pub name: String,
+    pub is_default: bool,
+    pub configs: Vec<ConfigRange>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+pub fn device_name(device: &cpal::Device) -> String {
+    device
+        .name()
+        .unwrap_or_else(|_| "Unknown device".to_string())
+}
+
+/// Все доступные аудиосистемы и их устройства вывода
+pub fn list_hosts() -> Vec<HostInfo> {
+    let default_host = cpal::default_host().id();
+
+    cpal::available_hosts()
+        .into_iter()
+        .filter_map(|id| cpal::host_from_id(id).ok())
+        .map(|host| {
+            let default_device = host.default_output_device().map(|d| device_name(&d));
+            let devices = host
+                .output_devices()
+                .map(|devices| {
+                    devices
+                        .map(|device| {
+                            let name = device_name(&device);
+                            let configs = device
+                                .supported_output_configs()
+                                .map(|configs| {
+                                    configs
+                                        .map(|c| ConfigRange {
+                                            channels: c.channels(),
+                                            min_sample_rate: c.min_sample_rate().0,
+                                            max_sample_rate: c.max_sample_rate().0,
+                                            sample_format: c.sample_format().to_string(),
+                                        })
+                                        .collect()
+                                })
+                                .unwrap_or_default();
+                            DeviceInfo {
+                                is_default: default_device.as_deref() == Some(name.as_str()),
+                                name,
+                                configs,
+                            }
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            HostInfo {
+                name: host.id().name().to_string(),
+                is_default: host.id() == default_host,
+                devices,
+            }
+        })
+        .collect()
+}
+
+/// Открывает выбранное устройство. Если его нет, берёт устройство
+/// по умолчанию; второй элемент кортежа сообщает о такой подмене.
+pub fn open(selection: &OutputDevice) -> Result<(cpal::Device, bool), PlayerError> {
+    let host = selection
+        .host
+        .as_deref()
+        .and_then(|name| {
+            cpal::available_hosts()
+                .into_iter()
+                .find(|id| id.name() == name)
+        })
+        .and_then(|id| cpal::host_from_id(id).ok())
+        .unwrap_or_else(cpal::default_host);
+
+    if let Some(name) = &selection.name {
+        let found = host
+            .output_devices()
+            .ok()
+            .and_then(|mut devices| devices.find(|d| d.name().ok().as_ref() == Some(name)));
+        if let Some(device) = found {
+            return Ok((device, false));
+        }
+    }
+
+    let device = host
+        .default_output_device()
+        .or_else(|| cpal::default_host().default_output_device())
+        .ok_or(PlayerError::NoOutputDevice)?;
+
+    Ok((device, selection.name.is_some()))
+}
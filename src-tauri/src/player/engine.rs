@@ -0,0 +1,717 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::crossfade::{Crossfade, Fade};
+use super::equalizer::{EqSettings, Equalizer};
+use super::gain::{GainMode, GainStage, ReplayGainSettings};
+use super::resample::ResampleQuality;
+use super::ring::Producer;
+use super::stretch::{pitch_ratio, SpeedMode, Stretcher};
+use super::timeline::{Marker, TrackInfo};
+use super::{EndReason, OutputSpec, PlayerError, PlayerEvent, PlayerSettings, Shared, Source};
+
+/// Сколько фреймов готовится за один проход
+const CHUNK_FRAMES: usize = 1024;
+/// Сколько ждать команду, когда кольцевой буфер полон
+const IDLE: Duration = Duration::from_millis(5);
+
+type Reply = Sender<Result<(), PlayerError>>;
+
+/// Команды потоку декодирования
+pub enum Command {
+    Load {
+        source: Source,
+        reply: Reply,
+    },
+    Enqueue(Source),
+    ClearQueue,
+    Seek {
+        sec: f32,
+        reply: Reply,
+    },
+    SetSpeed(f32),
+    SetSpeedMode(SpeedMode),
+    /// Сдвиг тона в полутонах
+    SetPitch(f32),
+    SetCrossfade(Crossfade),
+    SetEqualizer(EqSettings),
+    SetReplayGain(ReplayGainSettings),
+    /// Громкость трека без тегов ReplayGain, измеренная в фоне
+    SetAnalyzedGain {
+        path: String,
+        gain: f32,
+    },
+    SetOutput {
+        output: OutputSpec,
+        quality: ResampleQuality,
+        producer: Producer,
+    },
+    SetStateInterval(Duration),
+    /// Состояние изменилось вне потока: пауза, громкость
+    ReportState,
+    Stop,
+}
+
+/// Начало куска `buffer`, который принадлежит одному треку
+struct Segment {
+    frame: usize,
+    track: Arc<TrackInfo>,
+    time: f64,
+}
+
+/// Трек, на который декодер перешёл сам, но которого ещё не слышно
+struct Started {
+    track: Arc<TrackInfo>,
+    previous: String,
+    reason: EndReason,
+}
+
+/// Состояние потока декодирования. Аудиоколбэк его не видит:
+/// он получает готовые сэмплы через кольцевой буфер.
+pub struct Engine {
+    buffer: VecDeque<f32>,
+    /// Чей звук лежит в `buffer`, по возрастанию фреймов
+    segments: VecDeque<Segment>,
+    current: Source,
+    /// Следующие треки: декодер переходит на них без пересоздания потока
+    queue: VecDeque<Source>,
+    /// Переходы по очереди, о которых ещё не сообщили
+    starting: VecDeque<Started>,
+    crossfade: Crossfade,
+    fade: Option<Fade>,
+    scratch: VecDeque<f32>,
+    output: OutputSpec,
+    speed: f32,
+    speed_mode: SpeedMode,
+    /// Во сколько раз сдвинута частота
+    pitch: f32,
+    /// Растяжение между буфером декодера и курсором чтения,
+    /// если темп отличается от скорости курсора
+    stretch: Option<Stretcher>,
+    pos: f32,
+    eq: EqSettings,
+    equalizer: Equalizer,
+    /// Трек, под который настроены эквалайзер и нормализация
+    tuned_for: Option<Arc<TrackInfo>>,
+    replay_gain: ReplayGainSettings,
+    /// Измеренное усиление треков без тегов, дБ
+    analyzed: HashMap<String, f32>,
+    gain: GainStage,
+    /// Подготовленные сэмплы, ещё не поместившиеся в кольцевой буфер
+    rendered: Vec<f32>,
+    rendered_pos: usize,
+    /// Номер в кольцевом буфере первого фрейма `rendered`
+    rendered_frame: u64,
+    markers: Vec<Marker>,
+    producer: Producer,
+    shared: Arc<Shared>,
+    commands: Receiver<Command>,
+    events: Sender<PlayerEvent>,
+    state_interval: Duration,
+    state_sent: Instant,
+    /// Состояние изменилось, интерфейсу надо сообщить не дожидаясь такта
+    state_changed: bool,
+}
+
+impl Engine {
+    pub fn new(
+        current: Source,
+        settings: &PlayerSettings,
+        output: OutputSpec,
+        producer: Producer,
+        shared: Arc<Shared>,
+        commands: Receiver<Command>,
+        events: Sender<PlayerEvent>,
+    ) -> Self {
+        let mut engine = Engine {
+            buffer: VecDeque::with_capacity(8192),
+            segments: VecDeque::new(),
+            current,
+            queue: VecDeque::new(),
+            starting: VecDeque::new(),
+            crossfade: settings.crossfade,
+            fade: None,
+            scratch: VecDeque::with_capacity(8192),
+            output,
+            speed: 1.0,
+            speed_mode: settings.speed_mode,
+            pitch: pitch_ratio(settings.pitch),
+            stretch: None,
+            pos: 0.0,
+            eq: settings.equalizer.clone(),
+            equalizer: Equalizer::new(output.channels, output.sample_rate),
+            tuned_for: None,
+            replay_gain: settings.replay_gain,
+            analyzed: HashMap::new(),
+            gain: GainStage::new(output.channels, output.sample_rate),
+            rendered: Vec::with_capacity(CHUNK_FRAMES * output.channels),
+            rendered_pos: 0,
+            rendered_frame: 0,
+            markers: Vec::new(),
+            producer,
+            shared,
+            commands,
+            events,
+            state_interval: Duration::from_millis(settings.state_interval),
+            state_sent: Instant::now(),
+            state_changed: true,
+        };
+        engine.flush();
+        engine.update_rates();
+        engine
+    }
+
+    pub fn run(mut self) {
+        loop {
+            loop {
+                match self.commands.try_recv() {
+                    Ok(Command::Stop) | Err(TryRecvError::Disconnected) => return,
+                    Ok(command) => self.handle(command),
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+
+            if !self.fill() {
+                // буфер полон или декодировать нечего
+                self.check_end();
+                match self.commands.recv_timeout(IDLE) {
+                    Ok(Command::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+                    Ok(command) => self.handle(command),
+                    Err(RecvTimeoutError::Timeout) => {}
+                }
+            }
+            self.check_started();
+            self.report_state();
+        }
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Load { source, reply } => {
+                self.queue.clear();
+                // ручное переключение отменяет несыгранные переходы
+                self.starting.clear();
+                self.switch_to(source);
+                let _ = reply.send(Ok(()));
+            }
+            Command::Enqueue(source) => {
+                self.queue.push_back(source);
+                self.shared.ended.store(false, Ordering::Relaxed);
+            }
+            Command::ClearQueue => self.queue.clear(),
+            Command::Seek { sec, reply } => {
+                let _ = reply.send(self.seek(sec));
+            }
+            Command::SetSpeed(speed) => {
+                self.speed = speed;
+                self.update_rates();
+                self.state_changed = true;
+            }
+            Command::SetSpeedMode(mode) => {
+                self.speed_mode = mode;
+                self.update_rates();
+            }
+            Command::SetPitch(semitones) => {
+                self.pitch = pitch_ratio(semitones);
+                self.update_rates();
+            }
+            Command::SetCrossfade(crossfade) => self.crossfade = crossfade,
+            Command::SetEqualizer(eq) => {
+                self.eq = eq;
+                self.apply_equalizer();
+            }
+            Command::SetReplayGain(replay_gain) => {
+                self.replay_gain = replay_gain;
+                self.apply_gain();
+            }
+            Command::SetAnalyzedGain { path, gain } => {
+                let current = self.tuned_for.as_ref().is_some_and(|t| t.path == path);
+                self.analyzed.insert(path, gain);
+                if current {
+                    self.apply_gain();
+                }
+            }
+            Command::SetOutput {
+                output,
+                quality,
+                producer,
+            } => self.set_output(output, quality, producer),
+            Command::SetStateInterval(interval) => self.state_interval = interval,
+            Command::ReportState => self.state_changed = true,
+            Command::Stop => {}
+        }
+    }
+
+    /// Заполняет кольцевой буфер. Возвращает `false`, если работы не было.
+    fn fill(&mut self) -> bool {
+        let mut progressed = false;
+
+        loop {
+            if self.rendered_pos < self.rendered.len() {
+                let written = self
+                    .producer
+                    .push_slice(&self.rendered[self.rendered_pos..]);
+                if written == 0 {
+                    return progressed;
+                }
+                self.rendered_pos += written;
+                let frame = self.rendered_frame + (self.rendered_pos / self.output.channels) as u64;
+                self.shared.timeline.set_written(frame);
+                progressed = true;
+                continue;
+            }
+
+            if !self.render() {
+                return progressed;
+            }
+        }
+    }
+
+    /// Сообщает о конце, когда последний трек декодирован
+    /// и устройство доиграло всё, что было в буфере
+    fn check_end(&mut self) {
+        // короткий трек мог пролететь между проверками
+        self.check_started();
+        let drained = !self.shared.ended.load(Ordering::Relaxed)
+            && self.current.is_finished()
+            && self.queue.is_empty()
+            && self.fade.is_none()
+            && self.rendered_pos >= self.rendered.len();
+        if !drained {
+            return;
+        }
+        let written = self.rendered_frame + (self.rendered.len() / self.output.channels) as u64;
+        if self.shared.timeline.heard() < written {
+            return;
+        }
+
+        self.shared.ended.store(true, Ordering::Relaxed);
+        self.state_changed = true;
+        let _ = self.events.send(PlayerEvent::TrackEnded {
+            path: self.current.path.clone(),
+            reason: self.end_reason(),
+        });
+    }
+
+    fn end_reason(&self) -> EndReason {
+        if self.current.is_failed() {
+            EndReason::DecodeError
+        } else {
+            EndReason::Finished
+        }
+    }
+
+    /// Запоминает переход на следующий трек очереди, пока `current` — прежний
+    fn announce(&mut self, next: &Source) {
+        self.starting.push_back(Started {
+            track: next.info.clone(),
+            previous: self.current.path.clone(),
+            reason: self.end_reason(),
+        });
+    }
+
+    /// Сообщает о переходах по очереди, когда новый трек стало слышно
+    fn check_started(&mut self) {
+        if self.starting.is_empty() {
+            return;
+        }
+        let Some((heard, _)) = self.shared.timeline.position() else {
+            return;
+        };
+        let Some(index) = self
+            .starting
+            .iter()
+            .position(|started| Arc::ptr_eq(&started.track, &heard))
+        else {
+            return;
+        };
+        for started in self.starting.drain(..=index) {
+            let _ = self.events.send(PlayerEvent::TrackStarted {
+                path: started.track.path.clone(),
+                previous: started.previous,
+                reason: started.reason,
+            });
+        }
+        self.state_changed = true;
+    }
+
+    /// Отправляет состояние при изменении, а пока звук идёт — с заданной частотой
+    fn report_state(&mut self) {
+        let playing = !self.shared.ended.load(Ordering::Relaxed)
+            && !self.shared.paused.load(Ordering::Relaxed);
+        let due = playing && self.state_sent.elapsed() >= self.state_interval;
+        if !self.state_changed && !due {
+            return;
+        }
+        self.state_changed = false;
+        self.state_sent = Instant::now();
+        let state = self.shared.state(self.output.sample_rate);
+        let _ = self.events.send(PlayerEvent::State(state));
+    }
+
+    /// Готовит следующий фрагмент с учётом скорости воспроизведения
+    fn render(&mut self) -> bool {
+        let channels = self.output.channels;
+        self.rendered_frame += (self.rendered.len() / channels) as u64;
+        self.rendered.clear();
+        self.rendered_pos = 0;
+        let step = self.speed as f64 / self.output.sample_rate as f64;
+
+        let rate = self.cursor_rate();
+
+        for i in 0..CHUNK_FRAMES {
+            let idx = self.pos.floor() as usize;
+            if self.prepare(idx + 2) < idx + 1 {
+                break;
+            }
+
+            // позиция во входе растяжения, то есть в буфере декодера
+            let input = match &self.stretch {
+                Some(stretch) => stretch.input_position(self.pos as f64),
+                None => self.pos as f64,
+            };
+
+            // отметка в начале фрагмента и на каждой границе треков
+            let mut crossed = i == 0;
+            while self.segments.len() > 1 && self.segments[1].frame as f64 <= input {
+                self.segments.pop_front();
+                crossed = true;
+            }
+            if crossed {
+                if let Some(segment) = self.segments.front() {
+                    let offset = (input - segment.frame as f64).max(0.0);
+                    self.markers.push(Marker {
+                        frame: self.rendered_frame + i as u64,
+                        track: segment.track.clone(),
+                        time: segment.time + offset / self.output.sample_rate as f64,
+                        step,
+                    });
+                }
+                self.follow_track();
+            }
+
+            let samples = match &self.stretch {
+                Some(stretch) => stretch.output(),
+                None => &self.buffer,
+            };
+            let frac = self.pos - idx as f32;
+            for ch in 0..channels {
+                let a = samples[idx * channels + ch];
+                let b = samples.get((idx + 1) * channels + ch).copied().unwrap_or(a);
+                self.rendered.push(a + (b - a) * frac);
+            }
+
+            self.pos += rate;
+            let drop = (self.pos.floor() as usize).min(samples.len() / channels);
+            self.pos -= drop as f32;
+            match &mut self.stretch {
+                Some(stretch) => {
+                    stretch.consume(drop);
+                    let drained = stretch.drainable().min(self.buffer.len() / channels);
+                    stretch.shift(drained);
+                    self.buffer.drain(..drained * channels);
+                    self.drain_segments(drained);
+                }
+                None => {
+                    self.buffer.drain(..drop * channels);
+                    self.drain_segments(drop);
+                }
+            }
+        }
+
+        self.equalizer.process(&mut self.rendered);
+        if self.replay_gain.mode != GainMode::Off {
+            self.gain.process(&mut self.rendered);
+        }
+        self.shared.timeline.record(self.markers.drain(..));
+        !self.rendered.is_empty()
+    }
+
+    /// Перенастраивает эквалайзер, когда в буфере начинается другой трек:
+    /// у него может быть свой пресет
+    fn follow_track(&mut self) {
+        let Some(segment) = self.segments.front() else {
+            return;
+        };
+        let same = self
+            .tuned_for
+            .as_ref()
+            .is_some_and(|track| Arc::ptr_eq(track, &segment.track));
+        if !same {
+            self.tuned_for = Some(segment.track.clone());
+            self.apply_equalizer();
+            self.apply_gain();
+        }
+    }
+
+    fn apply_gain(&mut self) {
+        let gain = self
+            .tuned_for
+            .as_deref()
+            .and_then(|track| self.track_gain(track));
+        self.gain.set_gain_db(gain.unwrap_or(0.0));
+    }
+
+    /// Усиление трека по тегам или по измерению, дБ
+    fn track_gain(&self, track: &TrackInfo) -> Option<f32> {
+        let tags = track.replay_gain;
+        let album = match self.replay_gain.mode {
+            GainMode::Off => return None,
+            GainMode::Track => false,
+            GainMode::Album => true,
+            GainMode::Auto => {
+                track.album.is_some()
+                    && self.queue.front().map(|next| &next.info.album) == Some(&track.album)
+            }
+        };
+        let tagged = if album {
+            tags.album.or(tags.track)
+        } else {
+            tags.track.or(tags.album)
+        };
+        // предусилитель одинаков для тегов и измерения, иначе треки разойдутся
+        tagged
+            .or_else(|| self.analyzed.get(&track.path).copied())
+            .map(|gain| gain + self.replay_gain.preamp)
+    }
+
+    fn apply_equalizer(&mut self) {
+        let preset = self.eq.resolve(self.tuned_for.as_deref());
+        self.equalizer.set(preset.as_ref());
+    }
+
+    /// Пытается подготовить `frames` фреймов для курсора чтения,
+    /// возвращает сколько их есть
+    fn prepare(&mut self, frames: usize) -> usize {
+        let channels = self.output.channels;
+        loop {
+            let ready = match &self.stretch {
+                Some(stretch) => stretch.output().len(),
+                None => self.buffer.len(),
+            } / channels;
+            if ready >= frames {
+                return ready;
+            }
+
+            if let Some(stretch) = &mut self.stretch {
+                if stretch.process(&self.buffer) {
+                    continue;
+                }
+            }
+            if !self.decode_next_packet() {
+                return match &mut self.stretch {
+                    Some(stretch) => {
+                        stretch.finish(&self.buffer);
+                        stretch.output().len() / channels
+                    }
+                    None => ready,
+                };
+            }
+        }
+    }
+
+    /// Скорость курсора чтения, она же итоговый сдвиг тона
+    fn cursor_rate(&self) -> f32 {
+        match self.speed_mode {
+            SpeedMode::Tape => self.speed * self.pitch,
+            SpeedMode::Stretch => self.pitch,
+        }
+    }
+
+    /// Включает растяжение, когда темп должен отличаться от скорости курсора
+    fn update_rates(&mut self) {
+        let tempo = (self.speed / self.cursor_rate()) as f64;
+        if (tempo - 1.0).abs() < 1e-6 {
+            // курсор возвращается в буфер декодера на то же место
+            if let Some(stretch) = self.stretch.take() {
+                self.pos = stretch.input_position(self.pos as f64) as f32;
+            }
+            return;
+        }
+
+        match &mut self.stretch {
+            Some(stretch) => stretch.set_tempo(tempo),
+            None => {
+                let mut stretch =
+                    Stretcher::new(self.output.channels, self.output.sample_rate, tempo);
+                stretch.reset(self.pos as f64);
+                self.pos = 0.0;
+                self.stretch = Some(stretch);
+            }
+        }
+    }
+
+    /// Сдвигает границы треков после удаления `frames` фреймов из буфера
+    fn drain_segments(&mut self, frames: usize) {
+        let rate = self.output.sample_rate as f64;
+        for segment in self.segments.iter_mut() {
+            if segment.frame >= frames {
+                segment.frame -= frames;
+            } else {
+                segment.time += (frames - segment.frame) as f64 / rate;
+                segment.frame = 0;
+            }
+        }
+    }
+
+    /// Отмечает, что дальше в буфер пойдёт звук `track` с позиции `time`
+    fn begin_segment(&mut self, track: Arc<TrackInfo>, time: f64) {
+        self.segments.push_back(Segment {
+            frame: self.buffer.len() / self.output.channels,
+            track,
+            time,
+        });
+    }
+
+    /// Декодирует следующий пакет. Возвращает `false`, когда треки кончились.
+    fn decode_next_packet(&mut self) -> bool {
+        if self.fade.is_none() && self.crossfade.is_enabled() {
+            self.maybe_start_crossfade();
+        }
+
+        if let Some(mut fade) = self.fade.take() {
+            self.scratch.clear();
+
+            let decoded = self.current.decode_next(&mut self.scratch);
+            if decoded {
+                fade.mix(
+                    self.scratch.make_contiguous(),
+                    self.current.channels,
+                    &mut self.buffer,
+                );
+            }
+
+            if decoded && !fade.is_complete() {
+                self.fade = Some(fade);
+            } else {
+                self.current = fade.finish(&mut self.buffer);
+            }
+            return true;
+        }
+
+        while !self.current.decode_next(&mut self.buffer) {
+            // текущий трек закончился — сразу берём сэмплы следующего
+            match self.queue.pop_front() {
+                Some(next) => {
+                    self.begin_segment(next.info.clone(), next.position());
+                    self.announce(&next);
+                    self.current = next;
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    /// Начинает переход, когда до конца текущего трека осталось
+    /// не больше длительности кроссфейда
+    fn maybe_start_crossfade(&mut self) {
+        let remaining = match self.current.remaining() {
+            Some(r) if r <= self.crossfade.duration => r,
+            _ => return,
+        };
+
+        let compatible = self
+            .queue
+            .front()
+            .is_some_and(|next| next.is_compatible(&self.current));
+        if !compatible {
+            // форматы не совпадают — остаётся бесшовный переход
+            return;
+        }
+
+        if let Some(next) = self.queue.pop_front() {
+            self.begin_segment(next.info.clone(), next.position());
+            self.announce(&next);
+            self.fade = Some(Fade::new(next, self.crossfade.curve, remaining));
+        }
+    }
+
+    /// Переключение вручную: короткий переход вместо резкого обрыва
+    fn switch_to(&mut self, next: Source) {
+        if let Some(fade) = self.fade.take() {
+            self.current = fade.finish(&mut self.buffer);
+        }
+
+        let paused = self.shared.paused.load(Ordering::Relaxed);
+        if self.crossfade.is_enabled()
+            && !paused
+            && !self.current.is_finished()
+            && next.is_compatible(&self.current)
+        {
+            let seconds = self.crossfade.skip_duration.min(self.crossfade.duration);
+            self.begin_segment(next.info.clone(), next.position());
+            self.fade = Some(Fade::new(next, self.crossfade.curve, seconds));
+        } else {
+            self.current = next;
+            self.flush();
+        }
+    }
+
+    fn seek(&mut self, sec: f32) -> Result<(), PlayerError> {
+        // перемотка отменяет переход: входящий трек возвращается в очередь
+        if let Some(fade) = self.fade.take() {
+            let mut incoming = fade.finish(&mut VecDeque::new());
+            incoming.seek(0.0)?;
+            self.queue.push_front(incoming);
+        }
+
+        self.current.seek(sec)?;
+        self.flush();
+        Ok(())
+    }
+
+    fn set_output(&mut self, output: OutputSpec, quality: ResampleQuality, producer: Producer) {
+        if let Some(fade) = self.fade.take() {
+            self.current = fade.finish(&mut VecDeque::new());
+        }
+
+        self.output = output;
+        self.current.set_output(output, quality);
+        for source in self.queue.iter_mut() {
+            source.set_output(output, quality);
+        }
+        self.producer = producer;
+        // окно растяжения и фильтры зависят от частоты устройства
+        self.stretch = None;
+        self.equalizer = Equalizer::new(output.channels, output.sample_rate);
+        self.gain = GainStage::new(output.channels, output.sample_rate);
+        self.apply_equalizer();
+        self.apply_gain();
+        self.flush();
+        self.update_rates();
+    }
+
+    /// Выбрасывает всё декодированное, включая ещё не сыгранное в колбэке.
+    /// Отсчёт позиции начинается заново с текущего места трека.
+    fn flush(&mut self) {
+        self.shared.ended.store(false, Ordering::Relaxed);
+        self.state_changed = true;
+        self.buffer.clear();
+        self.pos = 0.0;
+        self.rendered.clear();
+        self.rendered_pos = 0;
+        if let Some(stretch) = &mut self.stretch {
+            stretch.reset(0.0);
+        }
+        self.producer.discard();
+        self.rendered_frame = (self.producer.written() / self.output.channels) as u64;
+
+        self.segments.clear();
+        // после сброса слышно только текущий трек
+        self.starting
+            .retain(|started| Arc::ptr_eq(&started.track, &self.current.info));
+        let current = self.current.info.clone();
+        let time = self.current.position();
+        self.begin_segment(current.clone(), time);
+        self.shared.timeline.reset(Marker {
+            frame: self.rendered_frame,
+            track: current,
+            time,
+            step: self.speed as f64 / self.output.sample_rate as f64,
+        });
+    }
+}
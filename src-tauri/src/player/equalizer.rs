@@ -0,0 +1,436 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use super::timeline::TrackInfo;
+
+/// Центральные частоты графического эквалайзера, Гц
+pub const GRAPHIC_FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+/// Добротность полос графического эквалайзера: ширина около октавы
+const GRAPHIC_Q: f32 = 1.41;
+/// Предел усиления и ослабления полосы, дБ
+pub const MAX_GAIN_DB: f32 = 24.0;
+/// За сколько фреймов новые коэффициенты плавно сменяют старые
+const RAMP_FRAMES: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilterType {
+    Peaking,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass,
+}
+
+/// Полоса параметрического эквалайзера
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Band {
+    pub kind: FilterType,
+    /// Гц
+    pub frequency: f32,
+    /// дБ, для фильтров среза не используется
+    pub gain: f32,
+    pub q: f32,
+}
+
+impl Band {
+    fn is_valid(&self) -> bool {
+        self.frequency > 0.0 && self.q > 0.0 && self.gain.abs() <= MAX_GAIN_DB
+    }
+}
+
+/// Кривая эквалайзера: предусилитель, графические и свободные полосы
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EqPreset {
+    /// дБ
+    pub preamp: f32,
+    /// Усиление полос `GRAPHIC_FREQUENCIES`, дБ
+    pub graphic: [f32; 10],
+    pub parametric: Vec<Band>,
+}
+
+impl EqPreset {
+    pub fn is_valid(&self) -> bool {
+        self.preamp.abs() <= MAX_GAIN_DB
+            && self.graphic.iter().all(|g| g.abs() <= MAX_GAIN_DB)
+            && self.parametric.iter().all(Band::is_valid)
+    }
+
+    fn bands(&self) -> impl Iterator<Item = Band> + '_ {
+        let graphic = GRAPHIC_FREQUENCIES
+            .iter()
+            .zip(self.graphic)
+            .map(|(&frequency, gain)| Band {
+                kind: FilterType::Peaking,
+                frequency,
+                gain,
+                q: GRAPHIC_Q,
+            });
+        graphic.chain(self.parametric.iter().copied())
+    }
+}
+
+/// Встроенные пресеты: имя и усиление графических полос
+const BUILTIN_PRESETS: [(&str, [f32; 10]); 7] = [
+    ("flat", [0.0; 10]),
+    ("rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+    ("pop", [-1.0, 1.0, 3.0, 4.0, 3.0, 0.0, -1.0, -1.0, 0.0, 1.0]),
+    ("jazz", [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+    (
+        "classical",
+        [4.0, 3.0, 2.0, 1.0, -1.0, -1.0, 0.0, 2.0, 3.0, 4.0],
+    ),
+    (
+        "bass-boost",
+        [7.0, 6.0, 5.0, 3.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    ),
+    (
+        "vocal",
+        [-2.0, -3.0, -2.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0],
+    ),
+];
+
+/// Где действует пресет, назначенный вместо общего
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverrideScope {
+    Track,
+    Album,
+}
+
+/// Настройки эквалайзера вместе с пользовательскими пресетами
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct EqSettings {
+    pub enabled: bool,
+    /// Общая кривая, её правят ползунки интерфейса
+    pub current: EqPreset,
+    pub presets: HashMap<String, EqPreset>,
+    /// Путь трека → имя пресета
+    pub track_presets: HashMap<String, String>,
+    /// Альбом → имя пресета
+    pub album_presets: HashMap<String, String>,
+}
+
+impl EqSettings {
+    /// Имена всех пресетов: сначала встроенные, затем пользовательские
+    pub fn preset_names(&self) -> Vec<String> {
+        let mut user: Vec<_> = self.presets.keys().cloned().collect();
+        user.sort();
+        BUILTIN_PRESETS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .chain(user)
+            .collect()
+    }
+
+    pub fn preset(&self, name: &str) -> Option<EqPreset> {
+        if let Some(preset) = self.presets.get(name) {
+            return Some(preset.clone());
+        }
+        BUILTIN_PRESETS
+            .iter()
+            .find(|(builtin, _)| *builtin == name)
+            .map(|(_, graphic)| EqPreset {
+                graphic: *graphic,
+                ..EqPreset::default()
+            })
+    }
+
+    pub fn set_override(&mut self, scope: OverrideScope, key: String, preset: Option<String>) {
+        let overrides = match scope {
+            OverrideScope::Track => &mut self.track_presets,
+            OverrideScope::Album => &mut self.album_presets,
+        };
+        match preset {
+            Some(preset) => overrides.insert(key, preset),
+            None => overrides.remove(&key),
+        };
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.current.is_valid() && self.presets.values().all(EqPreset::is_valid)
+    }
+
+    /// Кривая для трека: назначенная треку, затем альбому, затем общая.
+    /// `None`, если эквалайзер выключен.
+    pub fn resolve(&self, track: Option<&TrackInfo>) -> Option<EqPreset> {
+        if !self.enabled {
+            return None;
+        }
+        let assigned = track.and_then(|track| {
+            self.track_presets.get(&track.path).or_else(|| {
+                track
+                    .album
+                    .as_ref()
+                    .and_then(|album| self.album_presets.get(album))
+            })
+        });
+        assigned
+            .and_then(|name| self.preset(name))
+            .or_else(|| Some(self.current.clone()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Coefficients {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+}
+
+impl Coefficients {
+    const IDENTITY: Coefficients = Coefficients {
+        b0: 1.0,
+        b1: 0.0,
+        b2: 0.0,
+        a1: 0.0,
+        a2: 0.0,
+    };
+
+    /// Формулы RBJ Audio EQ Cookbook
+    fn new(band: &Band, sample_rate: f32) -> Self {
+        let frequency = (band.frequency as f64).min(sample_rate as f64 * 0.49);
+        let w0 = 2.0 * PI * frequency / sample_rate as f64;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * band.q as f64);
+        let a = 10f64.powf(band.gain as f64 / 40.0);
+        let shelf = 2.0 * a.sqrt() * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.kind {
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf,
+            ),
+            FilterType::LowPass => (
+                (1.0 - cos) / 2.0,
+                1.0 - cos,
+                (1.0 - cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos) / 2.0,
+                -(1.0 + cos),
+                (1.0 + cos) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos,
+                1.0 - alpha,
+            ),
+        };
+
+        Coefficients {
+            b0: (b0 / a0) as f32,
+            b1: (b1 / a0) as f32,
+            b2: (b2 / a0) as f32,
+            a1: (a1 / a0) as f32,
+            a2: (a2 / a0) as f32,
+        }
+    }
+
+    fn lerp_step(from: &Self, to: &Self, frames: usize) -> Self {
+        let n = frames as f32;
+        Coefficients {
+            b0: (to.b0 - from.b0) / n,
+            b1: (to.b1 - from.b1) / n,
+            b2: (to.b2 - from.b2) / n,
+            a1: (to.a1 - from.a1) / n,
+            a2: (to.a2 - from.a2) / n,
+        }
+    }
+
+    fn add(&mut self, step: &Self) {
+        self.b0 += step.b0;
+        self.b1 += step.b1;
+        self.b2 += step.b2;
+        self.a1 += step.a1;
+        self.a2 += step.a2;
+    }
+}
+
+/// Биквадратный фильтр (транспонированная прямая форма II)
+/// с плавной сменой коэффициентов
+struct Filter {
+    current: Coefficients,
+    target: Coefficients,
+    step: Coefficients,
+    remaining: usize,
+    /// Состояние на каждый канал
+    state: Vec<[f32; 2]>,
+}
+
+impl Filter {
+    fn new(channels: usize) -> Self {
+        Filter {
+            current: Coefficients::IDENTITY,
+            target: Coefficients::IDENTITY,
+            step: Coefficients::IDENTITY,
+            remaining: 0,
+            state: vec![[0.0; 2]; channels],
+        }
+    }
+
+    fn retarget(&mut self, target: Coefficients) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        self.step = Coefficients::lerp_step(&self.current, &target, RAMP_FRAMES);
+        self.remaining = RAMP_FRAMES;
+    }
+
+    fn is_bypassed(&self) -> bool {
+        self.remaining == 0 && self.current == Coefficients::IDENTITY
+    }
+
+    fn process(&mut self, frame: &mut [f32]) {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.current = self.target;
+            } else {
+                self.current.add(&self.step);
+            }
+        }
+
+        let c = self.current;
+        for (sample, [z1, z2]) in frame.iter_mut().zip(self.state.iter_mut()) {
+            let x = *sample;
+            let y = c.b0 * x + *z1;
+            *z1 = c.b1 * x - c.a1 * y + *z2;
+            *z2 = c.b2 * x - c.a2 * y;
+            *sample = y;
+        }
+    }
+}
+
+/// Цепочка фильтров эквалайзера для чередующихся сэмплов
+pub struct Equalizer {
+    channels: usize,
+    sample_rate: f32,
+    /// Слоты только добавляются: убранная полоса плавно становится единичной
+    filters: Vec<Filter>,
+    preamp: f32,
+    preamp_target: f32,
+}
+
+impl Equalizer {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Equalizer {
+            channels,
+            sample_rate: sample_rate as f32,
+            filters: Vec::new(),
+            preamp: 1.0,
+            preamp_target: 1.0,
+        }
+    }
+
+    /// Применяет кривую; `None` выключает эквалайзер
+    pub fn set(&mut self, preset: Option<&EqPreset>) {
+        let bands: Vec<Band> = preset.map(|p| p.bands().collect()).unwrap_or_default();
+        while self.filters.len() < bands.len() {
+            self.filters.push(Filter::new(self.channels));
+        }
+        for (i, filter) in self.filters.iter_mut().enumerate() {
+            let target = bands.get(i).map_or(Coefficients::IDENTITY, |band| {
+                Coefficients::new(band, self.sample_rate)
+            });
+            filter.retarget(target);
+        }
+        self.preamp_target = preset.map_or(1.0, |p| 10f32.powf(p.preamp / 20.0));
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let settled = self.preamp == self.preamp_target;
+        if settled && self.preamp == 1.0 && self.filters.iter().all(Filter::is_bypassed) {
+            return;
+        }
+
+        let preamp_step = (self.preamp_target - self.preamp) / RAMP_FRAMES as f32;
+        for frame in samples.chunks_mut(self.channels) {
+            if self.preamp != self.preamp_target {
+                self.preamp += preamp_step;
+                if (self.preamp_target - self.preamp).abs() <= preamp_step.abs() {
+                    self.preamp = self.preamp_target;
+                }
+            }
+            for sample in frame.iter_mut() {
+                *sample *= self.preamp;
+            }
+            for filter in &mut self.filters {
+                filter.process(frame);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms_after(preset: &EqPreset, frequency: f32) -> f32 {
+        let rate = 48_000;
+        let mut equalizer = Equalizer::new(1, rate);
+        equalizer.set(Some(preset));
+
+        let mut samples: Vec<f32> = (0..rate)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / rate as f32).sin())
+            .collect();
+        equalizer.process(&mut samples);
+
+        let tail = &samples[samples.len() / 2..];
+        (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn shapes_frequency_response() {
+        let sine_rms = std::f32::consts::FRAC_1_SQRT_2;
+        assert!((rms_after(&EqPreset::default(), 1000.0) - sine_rms).abs() < 1e-3);
+
+        let boost = EqPreset {
+            graphic: [0.0, 0.0, 0.0, 0.0, 0.0, 6.0, 0.0, 0.0, 0.0, 0.0],
+            ..EqPreset::default()
+        };
+        let gain = rms_after(&boost, 1000.0) / sine_rms;
+        assert!((20.0 * gain.log10() - 6.0).abs() < 0.5);
+
+        let low_pass = EqPreset {
+            parametric: vec![Band {
+                kind: FilterType::LowPass,
+                frequency: 500.0,
+                gain: 0.0,
+                q: 0.707,
+            }],
+            ..EqPreset::default()
+        };
+        assert!(rms_after(&low_pass, 8000.0) < sine_rms * 0.05);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::meta::{StandardTagKey, Tag};
+
+use super::loudness::TruePeak;
+
+/// Потолок лимитера: −1 dBTP
+const CEILING: f32 = 0.891;
+/// Опережение лимитера, секунды
+const LOOKAHEAD_SECONDS: f32 = 0.002;
+/// Восстановление усиления после пика, секунды
+const RELEASE_SECONDS: f32 = 0.05;
+/// За сколько фреймов меняется усиление при смене трека
+const RAMP_FRAMES: usize = 2048;
+
+/// Какое усиление ReplayGain применять
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// Альбомное, если следующий трек из того же альбома, иначе трековое
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ReplayGainSettings {
+    pub mode: GainMode,
+    /// Добавка к усилению из тегов, дБ
+    pub preamp: f32,
+}
+
+/// Усиление из тегов файла, дБ относительно −18 LUFS
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReplayGain {
+    pub track: Option<f32>,
+    pub album: Option<f32>,
+}
+
+impl ReplayGain {
+    /// REPLAYGAIN_* и R128_* (Opus); при наличии обоих побеждает ReplayGain
+    pub fn from_tags(tags: &[Tag]) -> Self {
+        let mut gain = ReplayGain::default();
+        let (mut r128_track, mut r128_album) = (None, None);
+
+        for tag in tags {
+            let value = tag.value.to_string();
+            match tag.std_key {
+                Some(StandardTagKey::ReplayGainTrackGain) => gain.track = parse_gain(&value),
+                Some(StandardTagKey::ReplayGainAlbumGain) => gain.album = parse_gain(&value),
+                _ if tag.key.eq_ignore_ascii_case("R128_TRACK_GAIN") => {
+                    r128_track = parse_r128_gain(&value)
+                }
+                _ if tag.key.eq_ignore_ascii_case("R128_ALBUM_GAIN") => {
+                    r128_album = parse_r128_gain(&value)
+                }
+                _ => {}
+            }
+        }
+
+        gain.track = gain.track.or(r128_track);
+        gain.album = gain.album.or(r128_album);
+        gain
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.track.is_none() && self.album.is_none()
+    }
+}
+
+/// Разбирает значение вида `-6.54 dB`
+pub fn parse_gain(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let number = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .unwrap_or(value);
+    number.trim().parse().ok()
+}
+
+/// R128_*_GAIN — число Q7.8 относительно −23 LUFS, переводим к опоре −18 LUFS
+fn parse_r128_gain(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .map(|q| q as f32 / 256.0 + 5.0)
+}
+
+/// Лимитер истинного пика с опережением: огибающая успевает опуститься
+/// до пика, поэтому положительное усиление не даёт перегрузки
+pub struct Limiter {
+    channels: usize,
+    lookahead: usize,
+    delay: VecDeque<f32>,
+    peak: TruePeak,
+    /// Скользящий минимум требуемого усиления: (номер фрейма, усиление)
+    minimum: VecDeque<(usize, f32)>,
+    frame: usize,
+    envelope: f32,
+    release: f32,
+    /// Скользящее среднее огибающей — плавная атака длиной в опережение
+    smoothing: VecDeque<f32>,
+    smoothing_sum: f64,
+}
+
+impl Limiter {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        let lookahead = ((sample_rate as f32 * LOOKAHEAD_SECONDS) as usize).max(1);
+        Limiter {
+            channels,
+            lookahead,
+            delay: VecDeque::from(vec![0.0; lookahead * channels]),
+            peak: TruePeak::new(channels),
+            minimum: VecDeque::new(),
+            frame: 0,
+            envelope: 1.0,
+            release: 1.0 - (-1.0 / (RELEASE_SECONDS * sample_rate as f32)).exp(),
+            smoothing: VecDeque::from(vec![1.0; lookahead]),
+            smoothing_sum: lookahead as f64,
+        }
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            let peak = self.peak.push(frame);
+            let required = if peak > CEILING { CEILING / peak } else { 1.0 };
+
+            while self.minimum.back().is_some_and(|&(_, g)| g >= required) {
+                self.minimum.pop_back();
+            }
+            self.minimum.push_back((self.frame, required));
+            while self
+                .minimum
+                .front()
+                .is_some_and(|&(i, _)| i + self.lookahead <= self.frame)
+            {
+                self.minimum.pop_front();
+            }
+            self.frame += 1;
+
+            let target = self.minimum.front().map_or(1.0, |&(_, g)| g);
+            self.envelope = if target < self.envelope {
+                target
+            } else {
+                self.envelope + (target - self.envelope) * self.release
+            };
+
+            self.smoothing_sum += self.envelope as f64;
+            self.smoothing.push_back(self.envelope);
+            if let Some(old) = self.smoothing.pop_front() {
+                self.smoothing_sum -= old as f64;
+            }
+            let gain = (self.smoothing_sum / self.lookahead as f64) as f32;
+
+            for sample in frame.iter_mut() {
+                self.delay.push_back(*sample);
+                *sample = self.delay.pop_front().unwrap_or(0.0) * gain;
+            }
+        }
+    }
+}
+
+/// Усиление нормализации с плавной сменой и лимитер после него
+pub struct GainStage {
+    channels: usize,
+    gain: f32,
+    target: f32,
+    step: f32,
+    limiter: Limiter,
+}
+
+impl GainStage {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        GainStage {
+            channels,
+            gain: 1.0,
+            target: 1.0,
+            step: 0.0,
+            limiter: Limiter::new(channels, sample_rate),
+        }
+    }
+
+    pub fn set_gain_db(&mut self, db: f32) {
+        self.target = 10f32.powf(db / 20.0);
+        self.step = (self.target - self.gain) / RAMP_FRAMES as f32;
+    }
+
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for frame in samples.chunks_exact_mut(self.channels) {
+            if self.gain != self.target {
+                self.gain += self.step;
+                if (self.target - self.gain) * self.step.signum() <= 0.0 {
+                    self.gain = self.target;
+                }
+            }
+            for sample in frame.iter_mut() {
+                *sample *= self.gain;
+            }
+        }
+        self.limiter.process(samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tag_values() {
+        assert_eq!(parse_gain("-6.54 dB"), Some(-6.54));
+        assert_eq!(parse_gain(" +2.10 dB "), Some(2.1));
+        assert_eq!(parse_r128_gain("-512"), Some(3.0));
+    }
+
+    #[test]
+    fn limiter_keeps_boosted_signal_below_ceiling() {
+        let rate = 48_000;
+        let mut stage = GainStage::new(1, rate);
+        stage.gain = 4.0;
+        stage.target = 4.0;
+
+        let mut samples: Vec<f32> = (0..rate)
+            .map(|i| 0.9 * (2.0 * std::f32::consts::PI * 997.0 * i as f32 / rate as f32).sin())
+            .collect();
+        stage.process(&mut samples);
+
+        let peak = samples.iter().fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak <= CEILING + 1e-4);
+        assert!(peak > CEILING * 0.9);
+    }
+}
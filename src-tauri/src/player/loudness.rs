@@ -0,0 +1,407 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use symphonia::core::audio::Channels;
+
+use super::{PlayerError, Source};
+
+/// Громкость, к которой приводит ReplayGain 2.0, LUFS
+pub const REFERENCE_LUFS: f64 = -18.0;
+/// Абсолютный порог стробирования BS.1770, LUFS
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Относительный порог для интегральной громкости, LU
+const RELATIVE_GATE: f64 = -10.0;
+/// Относительный порог для диапазона громкости (EBU Tech 3342), LU
+const RANGE_GATE: f64 = -20.0;
+/// Верхняя граница гистограммы, LUFS
+const HISTOGRAM_MAX: f64 = 5.0;
+/// Шаг гистограммы, LU
+const HISTOGRAM_STEP: f64 = 0.1;
+
+/// Результат измерения по EBU R128
+#[derive(Clone, Copy, Debug)]
+pub struct Loudness {
+    /// Интегральная громкость, LUFS
+    pub integrated: f64,
+    /// Диапазон громкости (LRA), LU
+    pub range: f64,
+    /// Истинный пик, доля полной шкалы
+    pub true_peak: f32,
+}
+
+impl Loudness {
+    /// Усиление до опорной громкости ReplayGain, дБ
+    pub fn gain(&self) -> f32 {
+        (REFERENCE_LUFS - self.integrated) as f32
+    }
+}
+
+/// Оценка истинного пика: между соседними сэмплами
+/// кубическая интерполяция в трёх точках (приближение к 4× передискретизации)
+pub struct TruePeak {
+    history: Vec<[f32; 4]>,
+}
+
+impl TruePeak {
+    pub fn new(channels: usize) -> Self {
+        TruePeak {
+            history: vec![[0.0; 4]; channels],
+        }
+    }
+
+    /// Пик на отрезке, который заканчивается за фрейм до `frame`
+    pub fn push(&mut self, frame: &[f32]) -> f32 {
+        let mut peak = 0.0f32;
+        for (history, &sample) in self.history.iter_mut().zip(frame) {
+            history.rotate_left(1);
+            history[3] = sample;
+            let [p0, p1, p2, p3] = *history;
+            peak = peak.max(p1.abs());
+            for t in [0.25f32, 0.5, 0.75] {
+                // Катмулл — Ром
+                let value = 0.5
+                    * (2.0 * p1
+                        + (p2 - p0) * t
+                        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+                        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t);
+                peak = peak.max(value.abs());
+            }
+        }
+        peak
+    }
+}
+
+/// Фильтр K-взвешивания: полка +4 дБ и срез ниже 38 Гц.
+/// Коэффициенты пересчитываются для любой частоты, как в libebur128.
+struct KWeighting {
+    shelf: ([f64; 3], [f64; 3]),
+    highpass: ([f64; 3], [f64; 3]),
+    state: [[f64; 2]; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = (
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let (f0, q) = (38.13547087602444, 0.5003270373238773);
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let highpass = (
+            [1.0, -2.0, 1.0],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting {
+            shelf,
+            highpass,
+            state: [[0.0; 2]; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let mut y = x;
+        for (i, (b, a)) in [self.shelf, self.highpass].iter().enumerate() {
+            let [z1, z2] = &mut self.state[i];
+            let out = b[0] * y + *z1;
+            *z1 = b[1] * y - a[1] * out + *z2;
+            *z2 = b[2] * y - a[2] * out;
+            y = out;
+        }
+        y
+    }
+}
+
+/// Распределение громкости блоков с шагом 0.1 LU, как в libebur128.
+/// Гистограммы разных треков складываются — так измеряется альбом.
+#[derive(Clone, Debug)]
+struct Histogram {
+    counts: Vec<u64>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        let bins = ((HISTOGRAM_MAX - ABSOLUTE_GATE) / HISTOGRAM_STEP).round() as usize;
+        Histogram {
+            counts: vec![0; bins],
+        }
+    }
+
+    /// Громкость середины корзины, LUFS
+    fn lufs(index: usize) -> f64 {
+        ABSOLUTE_GATE + (index as f64 + 0.5) * HISTOGRAM_STEP
+    }
+
+    /// Блоки тише абсолютного порога отбрасываются сразу
+    fn add(&mut self, power: f64) {
+        let lufs = to_lufs(power);
+        if lufs <= ABSOLUTE_GATE {
+            return;
+        }
+        let index = ((lufs - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+        let last = self.counts.len() - 1;
+        self.counts[index.min(last)] += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+    }
+
+    /// Корзины громче `threshold`: (номер, число блоков)
+    fn above(&self, threshold: f64) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(move |&(i, &count)| count > 0 && Self::lufs(i) > threshold)
+            .map(|(i, &count)| (i, count))
+    }
+
+    /// Средняя мощность блоков громче `threshold`
+    fn mean_power(&self, threshold: f64) -> Option<f64> {
+        let (sum, count) = self
+            .above(threshold)
+            .fold((0.0, 0u64), |(sum, total), (i, count)| {
+                (sum + from_lufs(Self::lufs(i)) * count as f64, total + count)
+            });
+        (count > 0).then(|| sum / count as f64)
+    }
+
+    /// Громкость на доле `fraction` распределения блоков громче `threshold`
+    fn percentile(&self, threshold: f64, fraction: f64) -> Option<f64> {
+        let total: u64 = self.above(threshold).map(|(_, count)| count).sum();
+        if total == 0 {
+            return None;
+        }
+        let target = ((total - 1) as f64 * fraction).round() as u64;
+        let mut seen = 0;
+        for (i, count) in self.above(threshold) {
+            seen += count;
+            if seen > target {
+                return Some(Self::lufs(i));
+            }
+        }
+        None
+    }
+}
+
+/// Измеритель громкости по ITU-R BS.1770 и EBU Tech 3342:
+/// блоки 400 мс и окна 3 с, оба с шагом 100 мс
+pub struct LoudnessMeter {
+    channels: usize,
+    weights: Vec<f64>,
+    filters: Vec<KWeighting>,
+    /// Фреймов в 100-миллисекундном подблоке
+    sub_length: usize,
+    sub_position: usize,
+    sub_sum: f64,
+    /// Взвешенные суммы квадратов последних 30 подблоков
+    recent: VecDeque<f64>,
+    /// Блоки 400 мс — для интегральной громкости
+    momentary: Histogram,
+    /// Окна 3 с — для диапазона громкости
+    short_term: Histogram,
+    peak: TruePeak,
+    true_peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: u32, weights: Vec<f64>) -> Self {
+        let channels = weights.len();
+        LoudnessMeter {
+            channels,
+            filters: (0..channels)
+                .map(|_| KWeighting::new(sample_rate as f64))
+                .collect(),
+            weights,
+            sub_length: (sample_rate as usize / 10).max(1),
+            sub_position: 0,
+            sub_sum: 0.0,
+            recent: VecDeque::with_capacity(30),
+            momentary: Histogram::new(),
+            short_term: Histogram::new(),
+            peak: TruePeak::new(channels),
+            true_peak: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            self.true_peak = self.true_peak.max(self.peak.push(frame));
+            for ((filter, weight), &sample) in self.filters.iter_mut().zip(&self.weights).zip(frame)
+            {
+                let y = filter.process(sample as f64);
+                self.sub_sum += weight * y * y;
+            }
+
+            self.sub_position += 1;
+            if self.sub_position == self.sub_length {
+                self.finish_subblock();
+            }
+        }
+    }
+
+    fn finish_subblock(&mut self) {
+        if self.recent.len() == 30 {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(self.sub_sum);
+        self.sub_position = 0;
+        self.sub_sum = 0.0;
+
+        let length = self.recent.len();
+        if length >= 4 {
+            let sum: f64 = self.recent.range(length - 4..).sum();
+            self.momentary.add(sum / (4 * self.sub_length) as f64);
+        }
+        if length == 30 {
+            let sum: f64 = self.recent.iter().sum();
+            self.short_term.add(sum / (30 * self.sub_length) as f64);
+        }
+    }
+
+    /// Добавляет измерения другого трека: получается громкость альбома
+    pub fn merge(&mut self, other: &LoudnessMeter) {
+        self.momentary.merge(&other.momentary);
+        self.short_term.merge(&other.short_term);
+        self.true_peak = self.true_peak.max(other.true_peak);
+    }
+
+    /// Интегральная громкость, LUFS; `None`, если звучания не набралось
+    pub fn integrated(&self) -> Option<f64> {
+        let absolute = self.momentary.mean_power(ABSOLUTE_GATE)?;
+        self.momentary
+            .mean_power(to_lufs(absolute) + RELATIVE_GATE)
+            .map(to_lufs)
+    }
+
+    /// Диапазон громкости: от 10-го до 95-го процентиля окон 3 с, LU
+    pub fn range(&self) -> f64 {
+        let Some(absolute) = self.short_term.mean_power(ABSOLUTE_GATE) else {
+            return 0.0;
+        };
+        let gate = to_lufs(absolute) + RANGE_GATE;
+        match (
+            self.short_term.percentile(gate, 0.10),
+            self.short_term.percentile(gate, 0.95),
+        ) {
+            (Some(low), Some(high)) => high - low,
+            _ => 0.0,
+        }
+    }
+
+    pub fn loudness(&self) -> Option<Loudness> {
+        Some(Loudness {
+            integrated: self.integrated()?,
+            range: self.range(),
+            true_peak: self.true_peak,
+        })
+    }
+}
+
+fn to_lufs(power: f64) -> f64 {
+    -0.691 + 10.0 * power.max(1e-20).log10()
+}
+
+fn from_lufs(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+/// Веса каналов BS.1770: тыловые и боковые громче, LFE не учитывается
+pub fn channel_weights(channels: Channels) -> Vec<f64> {
+    let surround = Channels::REAR_LEFT
+        | Channels::REAR_RIGHT
+        | Channels::SIDE_LEFT
+        | Channels::SIDE_RIGHT
+        | Channels::REAR_CENTRE;
+    channels
+        .iter()
+        .map(|channel| {
+            if channel.intersects(Channels::LFE1 | Channels::LFE2) {
+                0.0
+            } else if channel.intersects(surround) {
+                1.41
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// Декодирует файл целиком в исходном формате и пропускает через измеритель
+pub fn analyze_file(path: &str) -> Result<LoudnessMeter, PlayerError> {
+    let mut source = Source::open_native(path)?;
+    let mut meter = LoudnessMeter::new(source.sample_rate as u32, source.channel_weights());
+    let mut decoded = VecDeque::new();
+    while source.decode_next(&mut decoded) {
+        meter.process(decoded.make_contiguous());
+        decoded.clear();
+    }
+    Ok(meter)
+}
+
+pub fn measure_file(path: &str) -> Result<Loudness, PlayerError> {
+    analyze_file(path)?.loudness().ok_or(PlayerError::NoTracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_reference_sine() {
+        // синус 1 кГц −20 dBFS в двух каналах: −20 LUFS по BS.1770
+        let rate = 48_000;
+        let amplitude = 10f32.powf(-20.0 / 20.0);
+        let samples: Vec<f32> = (0..rate * 5)
+            .flat_map(|i| {
+                let s = amplitude
+                    * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin();
+                [s, s]
+            })
+            .collect();
+
+        let mut meter = LoudnessMeter::new(rate as u32, vec![1.0, 1.0]);
+        meter.process(&samples);
+        let loudness = meter.loudness().unwrap();
+
+        assert!((loudness.integrated + 20.0).abs() < 0.1);
+        assert!(loudness.range < 0.2);
+        assert!((loudness.true_peak - amplitude).abs() < 0.01);
+    }
+
+    #[test]
+    fn album_combines_tracks() {
+        let rate = 48_000;
+        let sine = |amplitude: f32| -> Vec<f32> {
+            (0..rate * 10)
+                .map(|i| {
+                    amplitude * (2.0 * std::f32::consts::PI * 1000.0 * i as f32 / rate as f32).sin()
+                })
+                .collect()
+        };
+
+        // моно −23 и −33 LUFS: альбом между ними, диапазон около 10 LU
+        let mut loud = LoudnessMeter::new(rate as u32, vec![1.0]);
+        loud.process(&sine(0.1));
+        let mut quiet = LoudnessMeter::new(rate as u32, vec![1.0]);
+        quiet.process(&sine(0.0316));
+        loud.merge(&quiet);
+
+        let album = loud.loudness().unwrap();
+        assert!(album.integrated < -23.0 && album.integrated > -27.0);
+        assert!((album.range - 10.0).abs() < 0.5);
+    }
+}
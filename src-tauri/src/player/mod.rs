@@ -1,19 +1,43 @@
-use std::collections::VecDeque;
-use std::fs::File;
+mod channels;
+mod crossfade;
+mod device;
+mod engine;
+mod equalizer;
+mod gain;
+mod loudness;
+mod output;
+mod resample;
+mod ring;
+mod source;
+mod stretch;
+mod timeline;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Sender};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::thread::JoinHandle;
+use std::time::Duration;
 
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use symphonia::core::audio::SampleBuffer;
-use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use serde::{Deserialize, Serialize};
 use symphonia::core::errors::Error as SymphoniaError;
-use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
-use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
-use symphonia::core::meta::MetadataOptions;
-use symphonia::core::probe::Hint;
-use symphonia::core::units::Time;
 use thiserror::Error;
 
+pub use channels::{ChannelLayout, MixMode};
+pub use crossfade::{Crossfade, FadeCurve};
+pub use device::{list_hosts, HostInfo, OutputDevice};
+pub use equalizer::{EqPreset, EqSettings, OverrideScope};
+pub use gain::{parse_gain, GainMode, ReplayGainSettings};
+pub use loudness::{analyze_file, Loudness, LoudnessMeter, REFERENCE_LUFS};
+pub use resample::ResampleQuality;
+pub use source::Source;
+pub use stretch::SpeedMode;
+pub use timeline::Position;
+
+use engine::{Command, Engine};
+use timeline::{Timeline, TrackInfo};
+
 #[derive(Error, Debug)]
 pub enum PlayerError {
     #[error("IO error: {0}")]
@@ -34,221 +58,561 @@ pub enum PlayerError {
     MutexPoisoned,
     #[error("Seek failed")]
     SeekFailed,
+    #[error("Value out of range")]
+    OutOfRange,
+    #[error("No output device available")]
+    NoOutputDevice,
+    #[error("Decoder thread stopped")]
+    Disconnected,
 }
 
-pub struct Player {
-    pub state: Arc<Mutex<PlayerState>>,
-    pub stream: cpal::Stream,
+/// События плеера, которые приложение пересылает в интерфейс
+#[derive(Clone, Debug)]
+pub enum PlayerEvent {
+    /// Устройство пропало во время воспроизведения
+    DeviceLost { device: String },
+    /// Выбранного устройства нет, звук пошёл на устройство по умолчанию
+    DeviceFallback { requested: String, device: String },
+    /// Декодер сам перешёл на трек из очереди плеера, и его стало слышно
+    TrackStarted {
+        path: String,
+        previous: String,
+        reason: EndReason,
+    },
+    /// Трек доиграл до конца и больше в плеере ничего нет
+    TrackEnded { path: String, reason: EndReason },
+    /// Состояние воспроизведения: с заданной частотой и при каждом изменении
+    State(PlaybackState),
+}
+
+/// Почему закончился трек
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EndReason {
+    Finished,
+    /// Файл оборвался ошибкой чтения или декодирования
+    DecodeError,
+    /// Звук некуда выводить: устройство пропало, замены нет
+    DeviceLost,
+}
+
+/// Настройки, которые переживают смену трека и пересоздание плеера
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct PlayerSettings {
+    pub crossfade: Crossfade,
+    pub resample_quality: ResampleQuality,
+    /// Дизеринг при выводе на 8/16-битные устройства
+    pub dither: bool,
+    pub output_device: OutputDevice,
+    pub speed_mode: SpeedMode,
+    /// Сдвиг тона в полутонах
+    pub pitch: f32,
+    pub equalizer: EqSettings,
+    pub replay_gain: ReplayGainSettings,
+    /// Как часто во время игры отправлять состояние интерфейсу, мс
+    pub state_interval: u64,
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings {
+            crossfade: Crossfade::default(),
+            resample_quality: ResampleQuality::default(),
+            dither: true,
+            output_device: OutputDevice::default(),
+            speed_mode: SpeedMode::default(),
+            pitch: 0.0,
+            equalizer: EqSettings::default(),
+            replay_gain: ReplayGainSettings::default(),
+            state_interval: 250,
+        }
+    }
+}
+
+/// Формат потока вывода
+#[derive(Clone, Copy, Debug)]
+pub struct OutputSpec {
+    pub sample_rate: u32,
+    pub channels: usize,
+}
+
+impl OutputSpec {
+    fn from_config(config: &cpal::SupportedStreamConfig) -> Self {
+        OutputSpec {
+            sample_rate: config.sample_rate().0,
+            channels: config.channels() as usize,
+        }
+    }
+
+    /// Размер кольцевого буфера между декодером и устройством
+    fn ring_capacity(&self) -> usize {
+        (self.sample_rate as f32 * RING_SECONDS) as usize * self.channels
+    }
 }
 
-pub struct PlayerState {
-    pub buffer: VecDeque<f32>,
-    pub format: Box<dyn FormatReader>,
-    pub decoder: Box<dyn Decoder>,
+/// Запас декодированного звука, секунды
+const RING_SECONDS: f32 = 0.25;
+
+/// Допустимая частота отправки состояния, мс
+pub const STATE_INTERVALS: std::ops::RangeInclusive<u64> = 20..=5000;
+
+/// Снимок воспроизведения для интерфейса
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlaybackState {
+    /// Трек, который сейчас слышно
+    pub path: Option<String>,
+    pub position: f64,
+    pub duration: Option<f64>,
     pub paused: bool,
-    pub speed: f32,
+    /// Очередь плеера доиграна, звука больше нет
+    pub ended: bool,
     pub volume: f32,
-    pub start_time: Instant,
-    pub sample_rate: f32,
-    pub pos: f32,
-    pub last_ts: u64, // timestamp последнего пакета
+    pub speed: f32,
+    /// Сколько звука подготовлено впереди слышимого, секунды
+    pub buffered: f64,
+}
+
+/// Общее состояние потока декодирования, аудиоколбэка и команд.
+/// Колбэк читает только атомики.
+pub struct Shared {
+    pub paused: AtomicBool,
+    /// Последний трек доиграл, о конце уже сообщили
+    pub ended: AtomicBool,
+    volume: AtomicU32,
+    speed: AtomicU32,
+    pub dither: AtomicBool,
+    pub timeline: Timeline,
+}
+
+impl Shared {
+    fn new(dither: bool, paused: bool) -> Self {
+        Shared {
+            paused: AtomicBool::new(paused),
+            ended: AtomicBool::new(false),
+            volume: AtomicU32::new(1.0f32.to_bits()),
+            speed: AtomicU32::new(1.0f32.to_bits()),
+            dither: AtomicBool::new(dither),
+            timeline: Timeline::default(),
+        }
+    }
+
+    pub fn volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.store(volume.to_bits(), Ordering::Relaxed);
+    }
+
+    fn set_speed(&self, speed: f32) {
+        self.speed.store(speed.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Снимок из атомиков и отметок, без обращения к потоку декодирования
+    pub fn state(&self, sample_rate: u32) -> PlaybackState {
+        let heard = self.timeline.position();
+        PlaybackState {
+            path: heard.as_ref().map(|(track, _)| track.path.clone()),
+            position: heard.as_ref().map_or(0.0, |(_, time)| *time),
+            duration: heard.and_then(|(track, _)| track.duration),
+            paused: self.paused.load(Ordering::Relaxed),
+            ended: self.ended.load(Ordering::Relaxed),
+            volume: self.volume(),
+            speed: f32::from_bits(self.speed.load(Ordering::Relaxed)),
+            buffered: self.timeline.buffered() as f64 / sample_rate as f64,
+        }
+    }
+}
+
+/// Постоянное хранилище измеренной громкости: без него трек без тегов
+/// ReplayGain декодировался бы целиком при каждом запуске
+pub trait GainStore: Send + Sync {
+    /// Усиление трека, если он уже измерен и с тех пор не менялся
+    fn gain(&self, path: &str) -> Option<f32>;
+    fn store(&self, path: &str, loudness: Loudness);
+}
+
+pub struct Player {
+    pub stream: cpal::Stream,
+    shared: Arc<Shared>,
+    commands: Sender<Command>,
+    decoder: Option<JoinHandle<()>>,
+    settings: PlayerSettings,
+    output: OutputSpec,
+    device_name: String,
+    events: Sender<PlayerEvent>,
+    /// Измеренное усиление по путям; `None` — измерение идёт
+    loudness: Arc<Mutex<HashMap<String, Option<f32>>>>,
+    gains: Arc<dyn GainStore>,
 }
 
 impl Player {
-    pub fn new(path: &str) -> Result<Self, PlayerError> {
-        let src = File::open(path)?;
-        let mss = MediaSourceStream::new(Box::new(src), MediaSourceStreamOptions::default());
-
-        let hint = Hint::new();
-        let meta_opts: MetadataOptions = Default::default();
-        let fmt_opts: FormatOptions = Default::default();
-
-        let probed = symphonia::default::get_probe()
-            .format(&hint, mss, &fmt_opts, &meta_opts)
-            .map_err(PlayerError::Symphonia)?;
-
-        let format = probed.format;
-
-        let track = format
-            .tracks()
-            .iter()
-            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
-            .ok_or(PlayerError::NoTracks)?;
-
-        let dec_opts: DecoderOptions = Default::default();
-        let decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .map_err(|_| PlayerError::UnsupportedCodec)?;
-
-        let sample_rate = track
-            .codec_params
-            .sample_rate
-            .ok_or(PlayerError::UnsupportedFormat)? as f32;
-
-        let state = Arc::new(Mutex::new(PlayerState {
-            buffer: VecDeque::with_capacity(8192),
-            format,
-            decoder,
-            paused: false,
-            speed: 1.0,
-            volume: 1.0,
-            start_time: Instant::now(),
-            sample_rate,
-            pos: 0.0,
-            last_ts: 0,
-        }));
-
-        let host = cpal::default_host();
-        let device = host
-            .default_output_device()
-            .ok_or(PlayerError::UnsupportedFormat)?;
+    /// `paused` — поток вывода запускается на паузе, без единого звука
+    pub fn new(
+        path: &str,
+        settings: PlayerSettings,
+        events: Sender<PlayerEvent>,
+        gains: Arc<dyn GainStore>,
+        paused: bool,
+    ) -> Result<Self, PlayerError> {
+        let (device, config) = Player::open_device(&settings.output_device, &events)?;
+
+        let output = OutputSpec::from_config(&config);
+        let current = Source::open(path, output, settings.resample_quality)?;
+        let untagged = current.info.replay_gain.is_empty();
+
+        let shared = Arc::new(Shared::new(settings.dither, paused));
+        let (producer, consumer) = ring::ring_buffer(output.ring_capacity());
+        let (commands, receiver) = mpsc::channel();
+
+        let engine = Engine::new(
+            current,
+            &settings,
+            output,
+            producer,
+            shared.clone(),
+            receiver,
+            events.clone(),
+        );
+        let decoder = std::thread::Builder::new()
+            .name("musa-decoder".to_string())
+            .spawn(move || engine.run())?;
+
+        let device_name = device::device_name(&device);
+        let stream = output::build_stream(
+            &device,
+            &config,
+            consumer,
+            shared.clone(),
+            events.clone(),
+            device_name.clone(),
+        )?;
+        stream.play()?;
+
+        let player = Player {
+            stream,
+            shared,
+            commands,
+            decoder: Some(decoder),
+            settings,
+            output,
+            device_name,
+            events,
+            loudness: Arc::new(Mutex::new(HashMap::new())),
+            gains,
+        };
+        if untagged {
+            player.analyze(path);
+        }
+        Ok(player)
+    }
+
+    fn open_device(
+        selection: &OutputDevice,
+        events: &Sender<PlayerEvent>,
+    ) -> Result<(cpal::Device, cpal::SupportedStreamConfig), PlayerError> {
+        let (device, fell_back) = device::open(selection)?;
+        if fell_back {
+            let _ = events.send(PlayerEvent::DeviceFallback {
+                requested: selection.name.clone().unwrap_or_default(),
+                device: device::device_name(&device),
+            });
+        }
+
         let config = device
             .default_output_config()
             .map_err(|_| PlayerError::UnsupportedFormat)?;
+        Ok((device, config))
+    }
 
-        let state_clone = state.clone();
-        let stream = match config.sample_format() {
-            cpal::SampleFormat::F32 => device.build_output_stream(
-                &config.into(),
-                move |data: &mut [f32], _| {
-                    let mut s = match state_clone.lock() {
-                        Ok(s) => s,
-                        Err(_) => return,
-                    };
-                    Player::fill_buffer(&mut s, data);
-                },
-                move |err| eprintln!("Stream error: {err}"),
-                None,
-            )?,
-            _ => return Err(PlayerError::UnsupportedFormat),
-        };
+    fn send(&self, command: Command) -> Result<(), PlayerError> {
+        self.commands
+            .send(command)
+            .map_err(|_| PlayerError::Disconnected)
+    }
+
+    /// Просит поток декодирования сразу отправить новое состояние
+    fn report_state(&self) {
+        let _ = self.commands.send(Command::ReportState);
+    }
+
+    /// Отправляет команду и ждёт, пока поток декодирования её выполнит
+    fn request(
+        &self,
+        command: impl FnOnce(Sender<Result<(), PlayerError>>) -> Command,
+    ) -> Result<(), PlayerError> {
+        let (reply, response) = mpsc::channel();
+        self.send(command(reply))?;
+        response.recv().map_err(|_| PlayerError::Disconnected)?
+    }
+
+    pub fn device_name(&self) -> &str {
+        &self.device_name
+    }
+
+    /// Переносит воспроизведение на другое устройство с той же позиции
+    pub fn switch_device(&mut self, selection: &OutputDevice) -> Result<(), PlayerError> {
+        let (device, config) = Player::open_device(selection, &self.events)?;
+        let output = OutputSpec::from_config(&config);
 
+        if let Err(err) = self.stream.pause() {
+            eprintln!("Error pausing stream on device switch: {}", err);
+        }
+
+        let (producer, consumer) = ring::ring_buffer(output.ring_capacity());
+        self.send(Command::SetOutput {
+            output,
+            quality: self.settings.resample_quality,
+            producer,
+        })?;
+
+        let device_name = device::device_name(&device);
+        let stream = output::build_stream(
+            &device,
+            &config,
+            consumer,
+            self.shared.clone(),
+            self.events.clone(),
+            device_name.clone(),
+        )?;
         stream.play()?;
 
-        Ok(Player { state, stream })
+        self.stream = stream;
+        self.output = output;
+        self.device_name = device_name;
+        Ok(())
     }
 
-    fn decode_next_packet(state: &mut PlayerState) {
-        if let Ok(packet) = state.format.next_packet() {
-            state.last_ts = packet.ts(); // сохраняем ts
+    /// Открывает трек в формате текущего устройства вывода.
+    /// Файл читается в потоке команды, декодер в это время продолжает играть.
+    fn open_source(&self, path: &str) -> Result<Source, PlayerError> {
+        Source::open(path, self.output, self.settings.resample_quality)
+    }
 
-            if let Ok(decoded) = state.decoder.decode(&packet) {
-                let mut buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
-                buf.copy_interleaved_ref(decoded);
-                state.buffer.extend(buf.samples());
-            }
+    /// Заменяет текущий трек, не пересоздавая аудиопоток
+    pub fn load(&self, path: &str) -> Result<(), PlayerError> {
+        let source = self.open_source(path)?;
+        let untagged = source.info.replay_gain.is_empty();
+        self.request(|reply| Command::Load { source, reply })?;
+        if untagged {
+            self.analyze(path);
         }
+        Ok(())
     }
 
-    pub fn fill_buffer(state: &mut PlayerState, data: &mut [f32]) {
-        if state.paused {
-            data.fill(0.0);
+    /// Берёт из хранилища или измеряет в фоне громкость трека без тегов ReplayGain
+    fn analyze(&self, path: &str) {
+        if self.settings.replay_gain.mode == GainMode::Off {
             return;
         }
-
-        for d in data.iter_mut() {
-            if state.buffer.len() < 1024 {
-                Player::decode_next_packet(state);
+        let Ok(mut cache) = self.loudness.lock() else {
+            return;
+        };
+        match cache.get(path) {
+            Some(Some(gain)) => {
+                let _ = self.send(Command::SetAnalyzedGain {
+                    path: path.to_string(),
+                    gain: *gain,
+                });
+                return;
             }
-
-            let idx = state.pos.floor() as usize;
-            let frac = state.pos - idx as f32;
-
-            let sample = if idx + 1 < state.buffer.len() {
-                let a = state.buffer[idx];
-                let b = state.buffer[idx + 1];
-                a + (b - a) * frac
-            } else if idx < state.buffer.len() {
-                state.buffer[idx]
-            } else {
-                0.0
-            };
-
-            *d = sample * state.volume;
-            state.pos += state.speed;
-
-            if state.pos >= state.buffer.len() as f32 {
-                let drop = state.pos.floor() as usize;
-                let actual_drop = drop.min(state.buffer.len());
-                for _ in 0..actual_drop {
-                    state.buffer.pop_front();
+            // уже измеряется
+            Some(None) => return,
+            None => {}
+        }
+        if let Some(gain) = self.gains.gain(path) {
+            cache.insert(path.to_string(), Some(gain));
+            let _ = self.send(Command::SetAnalyzedGain {
+                path: path.to_string(),
+                gain,
+            });
+            return;
+        }
+        cache.insert(path.to_string(), None);
+        drop(cache);
+
+        let path = path.to_string();
+        let cache = self.loudness.clone();
+        let gains = self.gains.clone();
+        let commands = self.commands.clone();
+        let spawned = std::thread::Builder::new()
+            .name("musa-loudness".to_string())
+            .spawn(move || match loudness::measure_file(&path) {
+                Ok(loudness) => {
+                    let gain = loudness.gain();
+                    gains.store(&path, loudness);
+                    if let Ok(mut cache) = cache.lock() {
+                        cache.insert(path.clone(), Some(gain));
+                    }
+                    let _ = commands.send(Command::SetAnalyzedGain { path, gain });
                 }
-                state.pos -= actual_drop as f32;
+                Err(err) => {
+                    eprintln!("Loudness analysis failed for {path}: {err}");
+                    if let Ok(mut cache) = cache.lock() {
+                        cache.remove(&path);
+                    }
+                }
+            });
+        if let Err(err) = spawned {
+            eprintln!("Failed to start loudness analysis: {err}");
+        }
+    }
+
+    pub fn set_crossfade(&mut self, crossfade: Crossfade) -> Result<(), PlayerError> {
+        if !crossfade.is_valid() {
+            return Err(PlayerError::OutOfRange);
+        }
+        self.settings.crossfade = crossfade;
+        self.send(Command::SetCrossfade(crossfade))
+    }
+
+    /// Меняет кривую эквалайзера на лету, без щелчков
+    pub fn set_equalizer(&mut self, eq: EqSettings) -> Result<(), PlayerError> {
+        if !eq.is_valid() {
+            return Err(PlayerError::OutOfRange);
+        }
+        self.settings.equalizer = eq.clone();
+        self.send(Command::SetEqualizer(eq))
+    }
+
+    pub fn set_replay_gain(&mut self, replay_gain: ReplayGainSettings) -> Result<(), PlayerError> {
+        if !(-15.0..=15.0).contains(&replay_gain.preamp) {
+            return Err(PlayerError::OutOfRange);
+        }
+        self.settings.replay_gain = replay_gain;
+        self.send(Command::SetReplayGain(replay_gain))?;
+
+        // режим могли только что включить: текущему треку нужна громкость
+        if let Ok((track, _)) = self.heard() {
+            if track.replay_gain.is_empty() {
+                self.analyze(&track.path);
             }
         }
+        Ok(())
+    }
+
+    pub fn set_dither(&mut self, enabled: bool) -> Result<(), PlayerError> {
+        self.settings.dither = enabled;
+        self.shared.dither.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Качество применяется к трекам, открытым после вызова
+    pub fn set_resample_quality(&mut self, quality: ResampleQuality) -> Result<(), PlayerError> {
+        self.settings.resample_quality = quality;
+        Ok(())
+    }
+
+    /// Добавляет трек в очередь для бесшовного перехода
+    pub fn enqueue(&self, path: &str) -> Result<(), PlayerError> {
+        let source = self.open_source(path)?;
+        let untagged = source.info.replay_gain.is_empty();
+        self.send(Command::Enqueue(source))?;
+        if untagged {
+            self.analyze(path);
+        }
+        Ok(())
+    }
+
+    pub fn clear_queue(&self) -> Result<(), PlayerError> {
+        self.send(Command::ClearQueue)
+    }
+
+    /// Трек, который сейчас слышно, и позиция в нём
+    fn heard(&self) -> Result<(Arc<TrackInfo>, f64), PlayerError> {
+        self.shared.timeline.position().ok_or(PlayerError::NoTracks)
+    }
+
+    /// Путь трека, который сейчас звучит
+    pub fn current_path(&self) -> Result<String, PlayerError> {
+        Ok(self.heard()?.0.path.clone())
+    }
+
+    /// Раскладка каналов текущего трека
+    pub fn channel_layout(&self) -> Result<ChannelLayout, PlayerError> {
+        Ok(self.heard()?.0.layout.clone())
+    }
+
+    /// Позиция с точностью до фрейма, отданного устройству
+    pub fn position(&self) -> Result<Position, PlayerError> {
+        let (track, position) = self.heard()?;
+        Ok(Position {
+            path: track.path.clone(),
+            position,
+            duration: track.duration,
+        })
     }
 
     pub fn pause(&self) -> Result<(), PlayerError> {
-        let mut s = self.state.lock().map_err(|_| PlayerError::MutexPoisoned)?;
-        s.paused = true;
+        self.shared.paused.store(true, Ordering::Relaxed);
+        self.report_state();
         Ok(())
     }
 
     pub fn resume(&self) -> Result<(), PlayerError> {
-        let mut s = self.state.lock().map_err(|_| PlayerError::MutexPoisoned)?;
-        s.paused = false;
+        self.shared.paused.store(false, Ordering::Relaxed);
+        self.report_state();
         Ok(())
     }
 
     pub fn set_volume(&self, volume: f32) -> Result<(), PlayerError> {
-        let mut s = self.state.lock().map_err(|_| PlayerError::MutexPoisoned)?;
         if (0.0..=1.0).contains(&volume) {
-            s.volume = volume;
+            self.shared.set_volume(volume);
+            self.report_state();
             Ok(())
         } else {
-            Err(PlayerError::UnsupportedFormat)
+            Err(PlayerError::OutOfRange)
         }
     }
 
     pub fn set_speed(&self, speed: f32) -> Result<(), PlayerError> {
-        let mut s = self.state.lock().map_err(|_| PlayerError::MutexPoisoned)?;
         if speed > 0.0 && speed <= 4.0 {
-            s.speed = speed;
-            Ok(())
+            self.shared.set_speed(speed);
+            self.send(Command::SetSpeed(speed))
         } else {
-            Err(PlayerError::UnsupportedFormat)
+            Err(PlayerError::OutOfRange)
         }
     }
 
-    pub fn seek(&self, sec: f32) -> Result<f32, PlayerError> {
-        let mut s = self.state.lock().map_err(|_| PlayerError::MutexPoisoned)?;
-        if sec < 0.0 {
-            return Err(PlayerError::UnsupportedFormat);
-        }
+    pub fn set_speed_mode(&mut self, mode: SpeedMode) -> Result<(), PlayerError> {
+        self.settings.speed_mode = mode;
+        self.send(Command::SetSpeedMode(mode))
+    }
 
-        s.format
-            .seek(
-                SeekMode::Coarse,
-                SeekTo::Time {
-                    time: Time::from(sec as f64),
-                    track_id: None,
-                },
-            )
-            .map_err(|_| PlayerError::SeekFailed)?;
-
-        s.decoder.reset();
-        s.buffer.clear();
-        s.pos = 0.0;
-
-        // Декодируем несколько пакетов
-        for _ in 0..5 {
-            if s.buffer.len() < 8192 {
-                Player::decode_next_packet(&mut s);
-            } else {
-                break;
-            }
+    /// Сдвиг тона в полутонах, не больше октавы в каждую сторону
+    pub fn set_pitch(&mut self, semitones: f32) -> Result<(), PlayerError> {
+        if !(-12.0..=12.0).contains(&semitones) {
+            return Err(PlayerError::OutOfRange);
         }
+        self.settings.pitch = semitones;
+        self.send(Command::SetPitch(semitones))
+    }
 
+    pub fn seek(&self, sec: f32) -> Result<f32, PlayerError> {
+        if sec < 0.0 {
+            return Err(PlayerError::OutOfRange);
+        }
+        self.request(|reply| Command::Seek { sec, reply })?;
         Ok(sec)
     }
 
     pub fn current_time(&self) -> Result<f32, PlayerError> {
-        let s = self.state.lock().map_err(|_| PlayerError::MutexPoisoned)?;
+        Ok(self.heard()?.1 as f32)
+    }
 
-        let packet_time = s.last_ts as f32 / s.sample_rate;
-        let buffer_offset = s.pos / s.sample_rate;
+    pub fn state(&self) -> PlaybackState {
+        self.shared.state(self.output.sample_rate)
+    }
 
-        Ok(packet_time + buffer_offset)
+    /// Частота отправки состояния во время игры, мс
+    pub fn set_state_interval(&mut self, interval: u64) -> Result<(), PlayerError> {
+        if !STATE_INTERVALS.contains(&interval) {
+            return Err(PlayerError::OutOfRange);
+        }
+        self.settings.state_interval = interval;
+        self.send(Command::SetStateInterval(Duration::from_millis(interval)))
     }
 }
 
@@ -257,5 +621,10 @@ impl Drop for Player {
         if let Err(err) = self.stream.pause() {
             eprintln!("Error pausing stream on drop: {}", err);
         }
+
+        let _ = self.commands.send(Command::Stop);
+        if let Some(decoder) = self.decoder.take() {
+            let _ = decoder.join();
+        }
     }
 }
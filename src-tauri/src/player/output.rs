@@ -0,0 +1,151 @@
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use cpal::traits::DeviceTrait;
+use cpal::{
+    FromSample, SampleFormat, SizedSample, StreamError, SupportedBufferSize, SupportedStreamConfig,
+    I24,
+};
+
+use super::ring::Consumer;
+use super::{PlayerError, PlayerEvent, Shared};
+
+/// Наибольший буфер смешивания в фреймах; столько же берётся,
+/// если устройство не сообщает размер своего буфера
+const MAX_MIX_FRAMES: u32 = 8192;
+
+/// TPDF-дизеринг: треугольный шум амплитудой ±1 младший разряд
+/// маскирует ошибку квантования при переходе к 8/16 битам
+pub struct Dither {
+    lsb: f32,
+    seed: u32,
+}
+
+impl Dither {
+    /// Дизеринг нужен только целочисленным форматам не больше 16 бит
+    pub fn for_format(format: SampleFormat) -> Option<Self> {
+        if format.is_float() || format.sample_size() > 2 {
+            return None;
+        }
+        let bits = format.sample_size() * 8;
+        Some(Dither {
+            lsb: 1.0 / (1u32 << (bits - 1)) as f32,
+            seed: 0x9E37_79B9,
+        })
+    }
+
+    /// Равномерное число в [0, 1) (xorshift32)
+    fn next(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    pub fn apply(&mut self, sample: f32) -> f32 {
+        sample + (self.next() - self.next()) * self.lsb
+    }
+}
+
+/// Открывает поток в родном формате устройства
+pub fn build_stream(
+    device: &cpal::Device,
+    config: &SupportedStreamConfig,
+    consumer: Consumer,
+    shared: Arc<Shared>,
+    events: Sender<PlayerEvent>,
+    device_name: String,
+) -> Result<cpal::Stream, PlayerError> {
+    // ошибки потока приходят из аудиопотока: сообщаем о них приложению
+    let on_error = move |err: StreamError| match err {
+        StreamError::DeviceNotAvailable => {
+            let _ = events.send(PlayerEvent::DeviceLost {
+                device: device_name.clone(),
+            });
+        }
+        err => eprintln!("Stream error: {err}"),
+    };
+
+    match config.sample_format() {
+        SampleFormat::I8 => build::<i8>(device, config, consumer, shared, on_error),
+        SampleFormat::I16 => build::<i16>(device, config, consumer, shared, on_error),
+        SampleFormat::I24 => build::<I24>(device, config, consumer, shared, on_error),
+        SampleFormat::I32 => build::<i32>(device, config, consumer, shared, on_error),
+        SampleFormat::I64 => build::<i64>(device, config, consumer, shared, on_error),
+        SampleFormat::U8 => build::<u8>(device, config, consumer, shared, on_error),
+        SampleFormat::U16 => build::<u16>(device, config, consumer, shared, on_error),
+        SampleFormat::U32 => build::<u32>(device, config, consumer, shared, on_error),
+        SampleFormat::U64 => build::<u64>(device, config, consumer, shared, on_error),
+        SampleFormat::F32 => build::<f32>(device, config, consumer, shared, on_error),
+        SampleFormat::F64 => build::<f64>(device, config, consumer, shared, on_error),
+        _ => Err(PlayerError::UnsupportedFormat),
+    }
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &SupportedStreamConfig,
+    mut consumer: Consumer,
+    shared: Arc<Shared>,
+    on_error: impl FnMut(StreamError) + Send + 'static,
+) -> Result<cpal::Stream, PlayerError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let mut dither = Dither::for_format(config.sample_format());
+    let channels = config.channels().max(1) as usize;
+    let sample_rate = config.sample_rate().0 as f64;
+    // выделяется здесь, в аудиопотоке буфер только нарезается
+    let frames = match config.buffer_size() {
+        SupportedBufferSize::Range { max, .. } => (*max).clamp(1, MAX_MIX_FRAMES),
+        SupportedBufferSize::Unknown => MAX_MIX_FRAMES,
+    };
+    let mut mix = vec![0.0f32; frames as usize * channels];
+
+    let stream = device.build_output_stream(
+        &config.config(),
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            // эти сэмплы зазвучат через задержку устройства
+            let timestamp = info.timestamp();
+            let latency = timestamp
+                .playback
+                .duration_since(&timestamp.callback)
+                .map_or(0, |d| (d.as_secs_f64() * sample_rate) as u64);
+            shared
+                .timeline
+                .set_played((consumer.position() / channels) as u64, latency);
+
+            // в реальном времени: только кольцевой буфер и атомики
+            let paused = shared.paused.load(Ordering::Relaxed);
+            let volume = shared.volume();
+            let dithering = shared.dither.load(Ordering::Relaxed);
+
+            // запрос больше буфера смешивания обрабатывается по частям
+            for data in data.chunks_mut(mix.len()) {
+                let mix = &mut mix[..data.len()];
+                if paused {
+                    mix.fill(0.0);
+                } else {
+                    let read = consumer.pop_slice(mix);
+                    mix[read..].fill(0.0);
+                    for sample in &mut mix[..read] {
+                        *sample *= volume;
+                    }
+                }
+
+                for (out, &sample) in data.iter_mut().zip(mix.iter()) {
+                    let sample = match &mut dither {
+                        Some(d) if dithering => d.apply(sample),
+                        _ => sample,
+                    };
+                    *out = T::from_sample(sample.clamp(-1.0, 1.0));
+                }
+            }
+        },
+        on_error,
+        None,
+    )?;
+
+    Ok(stream)
+}
@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Количество фаз в таблице полифазного фильтра
+const PHASES: usize = 256;
+
+/// Качество передискретизации: длина sinc-фильтра
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ResampleQuality {
+    fn taps(self) -> usize {
+        match self {
+            ResampleQuality::Low => 8,
+            ResampleQuality::Medium => 32,
+            ResampleQuality::High => 64,
+        }
+    }
+}
+
+/// Полифазный передискретизатор с окном Блэкмана.
+/// Принимает и отдаёт чередующиеся (interleaved) сэмплы.
+pub struct Resampler {
+    channels: usize,
+    half: usize,
+    /// Шаг по входу на один выходной фрейм
+    step: f64,
+    /// Позиция следующего выходного фрейма в `history`, во фреймах
+    position: f64,
+    history: Vec<f32>,
+    table: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32, channels: usize, quality: ResampleQuality) -> Self {
+        let taps = quality.taps();
+        let half = taps / 2;
+        let step = from as f64 / to as f64;
+        // при понижении частоты срез опускаем ниже новой частоты Найквиста
+        let cutoff = (1.0 / step).min(1.0) * 0.95;
+
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            for tap in 0..taps {
+                let x = tap as f64 - (half as f64 - 1.0) - frac;
+                table.push((cutoff * sinc(cutoff * x) * blackman(x, half as f64)) as f32);
+            }
+        }
+
+        let mut resampler = Resampler {
+            channels,
+            half,
+            step,
+            position: 0.0,
+            history: Vec::new(),
+            table,
+        };
+        resampler.reset();
+        resampler
+    }
+
+    /// Сбрасывает историю, например после перемотки
+    pub fn reset(&mut self) {
+        self.history.clear();
+        // нули слева, чтобы первый выходной фрейм совпадал с первым входным
+        self.history.resize((self.half - 1) * self.channels, 0.0);
+        self.position = (self.half - 1) as f64;
+    }
+
+    pub fn process(&mut self, input: &[f32], out: &mut VecDeque<f32>) {
+        self.history.extend_from_slice(input);
+        let frames = self.history.len() / self.channels;
+        let taps = self.half * 2;
+
+        while (self.position as usize) + self.half < frames {
+            let index = self.position as usize;
+            let frac = self.position - index as f64;
+            let phase = frac * PHASES as f64;
+            let p = phase as usize;
+            let t = (phase - p as f64) as f32;
+
+            let first = (index + 1 - self.half) * self.channels;
+            let a = &self.table[p * taps..(p + 1) * taps];
+            let b = &self.table[(p + 1) * taps..(p + 2) * taps];
+
+            for ch in 0..self.channels {
+                let mut acc = 0.0f32;
+                for tap in 0..taps {
+                    let coeff = a[tap] + (b[tap] - a[tap]) * t;
+                    acc += self.history[first + tap * self.channels + ch] * coeff;
+                }
+                out.push_back(acc);
+            }
+
+            self.position += self.step;
+        }
+
+        // отбрасываем фреймы, которые больше не попадут в окно фильтра
+        let consumed = (self.position as usize + 1).saturating_sub(self.half);
+        if consumed > 0 {
+            self.history.drain(..consumed * self.channels);
+            self.position -= consumed as f64;
+        }
+    }
+
+    /// Выталкивает хвост фильтра в конце потока
+    pub fn flush(&mut self, out: &mut VecDeque<f32>) {
+        let silence = vec![0.0; self.half * self.channels];
+        self.process(&silence, out);
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn blackman(x: f64, half: f64) -> f64 {
+    let n = (x + half) / (2.0 * half);
+    if !(0.0..=1.0).contains(&n) {
+        return 0.0;
+    }
+    0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_duration_when_converting_rate() {
+        let mut resampler = Resampler::new(48_000, 44_100, 2, ResampleQuality::Medium);
+        let input = vec![0.5f32; 48_000 * 2];
+        let mut out = VecDeque::new();
+
+        resampler.process(&input, &mut out);
+        resampler.flush(&mut out);
+
+        let frames = out.len() / 2;
+        assert!((frames as i64 - 44_100).abs() < 64, "frames: {frames}");
+    }
+
+    #[test]
+    fn passes_dc_level_through() {
+        let mut resampler = Resampler::new(44_100, 48_000, 1, ResampleQuality::High);
+        let input = vec![1.0f32; 4096];
+        let mut out = VecDeque::new();
+
+        resampler.process(&input, &mut out);
+
+        let middle = out[out.len() / 2];
+        assert!((middle - 1.0).abs() < 0.01, "middle: {middle}");
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Кольцевой буфер сэмплов для одного писателя и одного читателя (SPSC).
+/// Счётчики только растут, индекс в массиве — остаток от деления.
+struct Inner {
+    slots: Box<[UnsafeCell<f32>]>,
+    /// Сколько сэмплов прочитано всего
+    head: AtomicUsize,
+    /// Сколько сэмплов записано всего
+    tail: AtomicUsize,
+    /// Всё, что записано раньше этой отметки, читатель должен пропустить
+    discard: AtomicUsize,
+}
+
+// Писатель трогает только свободные ячейки, читатель — только заполненные
+unsafe impl Sync for Inner {}
+unsafe impl Send for Inner {}
+
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+    let slots = (0..capacity.max(1)).map(|_| UnsafeCell::new(0.0)).collect();
+    let inner = Arc::new(Inner {
+        slots,
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+        discard: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            inner: inner.clone(),
+        },
+        Consumer { inner },
+    )
+}
+
+pub struct Producer {
+    inner: Arc<Inner>,
+}
+
+impl Producer {
+    /// Записывает сколько поместится, возвращает число записанных сэмплов
+    pub fn push_slice(&mut self, data: &[f32]) -> usize {
+        let capacity = self.inner.slots.len();
+        let head = self.inner.head.load(Ordering::Acquire);
+        let tail = self.inner.tail.load(Ordering::Relaxed);
+        let count = data.len().min(capacity - (tail - head));
+
+        for (i, &sample) in data[..count].iter().enumerate() {
+            // SAFETY: ячейки между tail и head + capacity читатель не трогает
+            unsafe { *self.inner.slots[(tail + i) % capacity].get() = sample };
+        }
+
+        self.inner.tail.store(tail + count, Ordering::Release);
+        count
+    }
+
+    /// Просит читателя выбросить всё записанное до этого момента
+    pub fn discard(&mut self) {
+        let tail = self.inner.tail.load(Ordering::Relaxed);
+        self.inner.discard.store(tail, Ordering::Release);
+    }
+
+    /// Сколько сэмплов ждёт чтения
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        let tail = self.inner.tail.load(Ordering::Relaxed);
+        let head = self.inner.head.load(Ordering::Acquire);
+        let discard = self.inner.discard.load(Ordering::Relaxed);
+        tail - head.max(discard)
+    }
+
+    /// Сколько сэмплов записано всего
+    pub fn written(&self) -> usize {
+        self.inner.tail.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Consumer {
+    inner: Arc<Inner>,
+}
+
+impl Consumer {
+    /// С какого сэмпла начнётся следующее чтение, с учётом сброса
+    pub fn position(&self) -> usize {
+        let discard = self.inner.discard.load(Ordering::Acquire);
+        self.inner.head.load(Ordering::Relaxed).max(discard)
+    }
+
+    /// Читает сколько есть, возвращает число прочитанных сэмплов
+    pub fn pop_slice(&mut self, out: &mut [f32]) -> usize {
+        let capacity = self.inner.slots.len();
+        // discard раньше tail: отметка сброса никогда не обгоняет увиденный tail
+        let discard = self.inner.discard.load(Ordering::Acquire);
+        let tail = self.inner.tail.load(Ordering::Acquire);
+        let head = self.inner.head.load(Ordering::Relaxed).max(discard);
+        let count = out.len().min(tail - head);
+
+        for (i, sample) in out[..count].iter_mut().enumerate() {
+            // SAFETY: ячейки между head и tail писатель не трогает
+            *sample = unsafe { *self.inner.slots[(head + i) % capacity].get() };
+        }
+
+        self.inner.head.store(head + count, Ordering::Release);
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around_capacity() {
+        let (mut producer, mut consumer) = ring_buffer(4);
+        let mut out = [0.0; 3];
+
+        assert_eq!(producer.push_slice(&[1.0, 2.0, 3.0]), 3);
+        assert_eq!(consumer.pop_slice(&mut out), 3);
+        assert_eq!(producer.push_slice(&[4.0, 5.0, 6.0, 7.0, 8.0]), 4);
+        assert_eq!(consumer.pop_slice(&mut out), 3);
+        assert_eq!(out, [4.0, 5.0, 6.0]);
+        assert_eq!(producer.len(), 1);
+    }
+
+    #[test]
+    fn discard_skips_written_samples() {
+        let (mut producer, mut consumer) = ring_buffer(8);
+        let mut out = [0.0; 4];
+
+        producer.push_slice(&[1.0, 2.0, 3.0]);
+        producer.discard();
+        producer.push_slice(&[9.0]);
+
+        assert_eq!(consumer.pop_slice(&mut out), 1);
+        assert_eq!(out[0], 9.0);
+    }
+
+    #[test]
+    fn survives_concurrent_use() {
+        let (mut producer, mut consumer) = ring_buffer(64);
+        let writer = std::thread::spawn(move || {
+            let mut next = 0.0f32;
+            while next < 10_000.0 {
+                if producer.push_slice(&[next]) == 1 {
+                    next += 1.0;
+                }
+            }
+        });
+
+        let mut expected = 0.0f32;
+        let mut out = [0.0; 16];
+        while expected < 10_000.0 {
+            let read = consumer.pop_slice(&mut out);
+            for &sample in &out[..read] {
+                assert_eq!(sample, expected);
+                expected += 1.0;
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn survives_concurrent_discard() {
+        let (mut producer, mut consumer) = ring_buffer(64);
+        let writer = std::thread::spawn(move || {
+            let mut next = 0.0f32;
+            while next < 10_000.0 {
+                if next % 100.0 == 0.0 {
+                    producer.discard();
+                }
+                if producer.push_slice(&[next]) == 1 {
+                    next += 1.0;
+                }
+            }
+        });
+
+        let mut last = -1.0f32;
+        let mut out = [0.0; 16];
+        while last < 9_999.0 {
+            let read = consumer.pop_slice(&mut out);
+            for &sample in &out[..read] {
+                assert!(sample > last);
+                last = sample;
+            }
+        }
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::{MediaSourceStream, MediaSourceStreamOptions};
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+use symphonia::core::probe::{Hint, ProbeResult};
+use symphonia::core::units::{Time, TimeBase};
+
+use super::channels::ChannelMap;
+use super::gain::ReplayGain;
+use super::loudness::channel_weights;
+use super::resample::{ResampleQuality, Resampler};
+use super::timeline::TrackInfo;
+use super::{OutputSpec, PlayerError};
+
+/// Открытый трек: демуксер и декодер одного файла.
+/// Сэмплы отдаются уже в частоте и раскладке каналов устройства вывода.
+pub struct Source {
+    pub path: String,
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    pub sample_rate: f32,
+    pub output_rate: f32,
+    /// Число каналов на выходе, после сведения
+    pub channels: usize,
+    pub info: Arc<TrackInfo>,
+    source_channels: Channels,
+    pub n_frames: Option<u64>,
+    time_base: Option<TimeBase>,
+    /// Номер следующего отдаваемого фрейма файла
+    frames_decoded: u64,
+    /// Фреймы перед точкой перемотки, которые декодер должен выбросить
+    skip_frames: u64,
+    channel_map: Option<ChannelMap>,
+    mapped: Vec<f32>,
+    resampler: Option<Resampler>,
+    finished: bool,
+    /// Поток оборвался ошибкой, а не концом файла
+    failed: bool,
+}
+
+impl Source {
+    pub fn open(
+        path: &str,
+        output: OutputSpec,
+        quality: ResampleQuality,
+    ) -> Result<Self, PlayerError> {
+        let mut source = Source::probe(path)?;
+        source.set_output(output, quality);
+        Ok(source)
+    }
+
+    /// Открывает трек без преобразований: частота и каналы как в файле
+    pub fn open_native(path: &str) -> Result<Self, PlayerError> {
+        let mut source = Source::probe(path)?;
+        let native = OutputSpec {
+            sample_rate: source.sample_rate as u32,
+            channels: source.source_channels.count(),
+        };
+        source.set_output(native, ResampleQuality::default());
+        Ok(source)
+    }
+
+    fn probe(path: &str) -> Result<Self, PlayerError> {
+        let src = File::open(path)?;
+        let mss = MediaSourceStream::new(Box::new(src), MediaSourceStreamOptions::default());
+
+        let mut hint = Hint::new();
+        if let Some(ext) = Path::new(path).extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let meta_opts: MetadataOptions = Default::default();
+        let fmt_opts: FormatOptions = Default::default();
+
+        let mut probed = symphonia::default::get_probe()
+            .format(&hint, mss, &fmt_opts, &meta_opts)
+            .map_err(PlayerError::Symphonia)?;
+
+        let (album, replay_gain) = read_tags(&mut probed);
+        let format = probed.format;
+
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or(PlayerError::NoTracks)?;
+
+        let dec_opts: DecoderOptions = Default::default();
+        let decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &dec_opts)
+            .map_err(|_| PlayerError::UnsupportedCodec)?;
+
+        let sample_rate = track
+            .codec_params
+            .sample_rate
+            .ok_or(PlayerError::UnsupportedFormat)?;
+        let source_channels = track
+            .codec_params
+            .channels
+            .or_else(|| track.codec_params.channel_layout.map(|l| l.into_channels()))
+            .ok_or(PlayerError::UnsupportedFormat)?;
+        let n_frames = track.codec_params.n_frames;
+        let time_base = track.codec_params.time_base;
+        let track_id = track.id;
+        let layout = ChannelMap::new(source_channels, source_channels.count()).layout;
+
+        Ok(Source {
+            path: path.to_string(),
+            format,
+            decoder,
+            track_id,
+            sample_rate: sample_rate as f32,
+            output_rate: 0.0,
+            channels: 0,
+            info: Arc::new(TrackInfo {
+                path: path.to_string(),
+                duration: n_frames.map(|n| n as f64 / sample_rate as f64),
+                layout,
+                album,
+                replay_gain,
+            }),
+            source_channels,
+            n_frames,
+            time_base,
+            frames_decoded: 0,
+            skip_frames: 0,
+            channel_map: None,
+            mapped: Vec::new(),
+            resampler: None,
+            finished: false,
+            failed: false,
+        })
+    }
+
+    /// Перестраивает сведение каналов и передискретизацию под новое устройство.
+    /// Позиция в файле сохраняется.
+    pub fn set_output(&mut self, output: OutputSpec, quality: ResampleQuality) {
+        let channel_map = ChannelMap::new(self.source_channels, output.channels);
+        self.info = Arc::new(TrackInfo {
+            path: self.path.clone(),
+            duration: self.info.duration,
+            layout: channel_map.layout.clone(),
+            album: self.info.album.clone(),
+            replay_gain: self.info.replay_gain,
+        });
+        self.channel_map = (!channel_map.is_passthrough()).then_some(channel_map);
+
+        let sample_rate = self.sample_rate as u32;
+        self.channels = output.channels;
+        self.output_rate = output.sample_rate as f32;
+        self.resampler = (sample_rate != output.sample_rate)
+            .then(|| Resampler::new(sample_rate, output.sample_rate, output.channels, quality));
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.failed
+    }
+
+    /// Сколько секунд осталось декодировать, если длина трека известна
+    pub fn remaining(&self) -> Option<f32> {
+        self.n_frames
+            .map(|n| n.saturating_sub(self.frames_decoded) as f32 / self.sample_rate)
+    }
+
+    /// Веса каналов файла для измерения громкости
+    pub fn channel_weights(&self) -> Vec<f64> {
+        channel_weights(self.source_channels)
+    }
+
+    /// Позиция в файле, с которой пойдут следующие сэмплы, секунды
+    pub fn position(&self) -> f64 {
+        self.frames_decoded as f64 / self.sample_rate as f64
+    }
+
+    /// Переводит метку времени трека во фреймы
+    fn ts_to_frames(&self, ts: u64) -> u64 {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.sample_rate as f64).round() as u64
+            }
+            None => ts,
+        }
+    }
+
+    /// Можно ли смешивать сэмплы двух треков без преобразования
+    pub fn is_compatible(&self, other: &Source) -> bool {
+        self.channels == other.channels && self.output_rate == other.output_rate
+    }
+
+    /// Декодирует следующий пакет в `out`.
+    /// Возвращает `false`, когда поток закончился.
+    pub fn decode_next(&mut self, out: &mut VecDeque<f32>) -> bool {
+        if self.finished {
+            return false;
+        }
+
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::ResetRequired) => {
+                    self.decoder.reset();
+                    continue;
+                }
+                Err(err) => {
+                    // конец файла демуксер сообщает как UnexpectedEof
+                    self.failed = !matches!(
+                        err,
+                        SymphoniaError::IoError(ref e) if e.kind() == io::ErrorKind::UnexpectedEof
+                    );
+                    return self.finish(out);
+                }
+            };
+
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            match self.decoder.decode(&packet) {
+                Ok(decoded) => {
+                    let frames = decoded.frames() as u64;
+                    // пре-ролл до точки перемотки отбрасываем целиком
+                    if self.skip_frames >= frames {
+                        self.skip_frames -= frames;
+                        continue;
+                    }
+                    let skip = std::mem::take(&mut self.skip_frames);
+                    self.frames_decoded += frames - skip;
+                    let channels = decoded.spec().channels.count();
+
+                    let mut buf =
+                        SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+                    buf.copy_interleaved_ref(decoded);
+                    let samples = &buf.samples()[skip as usize * channels..];
+
+                    let samples = match &self.channel_map {
+                        Some(map) => {
+                            map.apply(samples, &mut self.mapped);
+                            &self.mapped[..]
+                        }
+                        None => samples,
+                    };
+                    match &mut self.resampler {
+                        Some(resampler) => resampler.process(samples, out),
+                        None => out.extend(samples),
+                    }
+                    return true;
+                }
+                // битый пакет пропускаем
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(_) => {
+                    self.failed = true;
+                    return self.finish(out);
+                }
+            }
+        }
+    }
+
+    /// Отмечает конец потока, дописывая хвост передискретизатора
+    fn finish(&mut self, out: &mut VecDeque<f32>) -> bool {
+        self.finished = true;
+        match &mut self.resampler {
+            Some(resampler) => {
+                resampler.flush(out);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Точная перемотка: демуксер встаёт на пакет не позже цели,
+    /// лишние фреймы до неё выбрасываются при декодировании
+    pub fn seek(&mut self, sec: f32) -> Result<(), PlayerError> {
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::Time {
+                    time: Time::from(sec as f64),
+                    track_id: Some(self.track_id),
+                },
+            )
+            .map_err(|_| PlayerError::SeekFailed)?;
+
+        self.decoder.reset();
+        if let Some(resampler) = &mut self.resampler {
+            resampler.reset();
+        }
+        let actual = self.ts_to_frames(seeked.actual_ts);
+        let required = self.ts_to_frames(seeked.required_ts);
+        self.skip_frames = required.saturating_sub(actual);
+        self.frames_decoded = required;
+        self.finished = false;
+        self.failed = false;
+        Ok(())
+    }
+}
+
+/// Теги, которые нужны плееру: альбом и ReplayGain.
+/// Смотрим и в контейнер, и в ID3/APE перед ним.
+fn read_tags(probed: &mut ProbeResult) -> (Option<String>, ReplayGain) {
+    let mut tags: Vec<Tag> = Vec::new();
+    if let Some(revision) = probed.format.metadata().current() {
+        tags.extend_from_slice(revision.tags());
+    }
+    if let Some(metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.current() {
+            tags.extend_from_slice(revision.tags());
+        }
+    }
+
+    let album = tags
+        .iter()
+        .find(|tag| tag.std_key == Some(StandardTagKey::Album))
+        .map(|tag| tag.value.to_string());
+    (album, ReplayGain::from_tags(&tags))
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+/// Длина окна WSOLA, секунды
+const WINDOW_SECONDS: f32 = 0.04;
+/// Насколько далеко от идеальной позиции искать похожий фрагмент, секунды
+const TOLERANCE_SECONDS: f32 = 0.01;
+
+/// Как меняется скорость воспроизведения
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpeedMode {
+    /// Как магнитофон: вместе с темпом меняется высота тона
+    #[default]
+    Tape,
+    /// Темп меняется, высота тона сохраняется
+    Stretch,
+}
+
+/// Коэффициент частоты для сдвига на `semitones` полутонов
+pub fn pitch_ratio(semitones: f32) -> f32 {
+    2f32.powf(semitones / 12.0)
+}
+
+/// Растяжение по времени без изменения тона (WSOLA).
+/// Читает чередующиеся сэмплы из буфера движка, не забирая их:
+/// какие фреймы можно удалить, подсказывает `drainable`.
+pub struct Stretcher {
+    channels: usize,
+    window: Vec<f32>,
+    /// Шаг по выходу, фреймы; окно вдвое длиннее
+    hop: usize,
+    tolerance: usize,
+    /// Фреймов входа на фрейм выхода
+    tempo: f64,
+    /// Идеальная позиция следующего фрагмента во входе
+    analysis: f64,
+    /// Начало предыдущего выбранного фрагмента
+    prev: Option<usize>,
+    /// Вторая половина предыдущего фрагмента для наложения
+    overlap: Vec<f32>,
+    out: VecDeque<f32>,
+    /// Позиция во входе, которой соответствует первый фрейм `out`
+    head: f64,
+}
+
+impl Stretcher {
+    pub fn new(channels: usize, sample_rate: u32, tempo: f64) -> Self {
+        let hop = ((sample_rate as f32 * WINDOW_SECONDS) as usize / 2).max(1);
+        let window = (0..hop * 2)
+            .map(|i| 0.5 - 0.5 * (PI * i as f32 / hop as f32).cos())
+            .collect();
+        Stretcher {
+            channels,
+            window,
+            hop,
+            tolerance: (sample_rate as f32 * TOLERANCE_SECONDS) as usize,
+            tempo,
+            analysis: 0.0,
+            prev: None,
+            overlap: vec![0.0; hop * channels],
+            out: VecDeque::new(),
+            head: 0.0,
+        }
+    }
+
+    pub fn set_tempo(&mut self, tempo: f64) {
+        self.tempo = tempo;
+    }
+
+    /// Начинает заново с позиции `input` во входе
+    pub fn reset(&mut self, input: f64) {
+        self.analysis = input;
+        self.head = input;
+        self.prev = None;
+        self.overlap.fill(0.0);
+        self.out.clear();
+    }
+
+    pub fn output(&self) -> &VecDeque<f32> {
+        &self.out
+    }
+
+    /// Позиция во входе для дробного фрейма выхода
+    pub fn input_position(&self, frame: f64) -> f64 {
+        self.head + frame * self.tempo
+    }
+
+    /// Убирает `frames` прочитанных фреймов выхода
+    pub fn consume(&mut self, frames: usize) {
+        let frames = frames.min(self.out.len() / self.channels);
+        self.out.drain(..frames * self.channels);
+        self.head += frames as f64 * self.tempo;
+    }
+
+    /// Сколько первых фреймов входа больше не понадобится
+    pub fn drainable(&self) -> usize {
+        let search = self.analysis - self.tolerance as f64;
+        let mut keep = search.min(self.head).max(0.0) as usize;
+        if let Some(prev) = self.prev {
+            keep = keep.min(prev);
+        }
+        keep
+    }
+
+    /// Сдвигает позиции после удаления `frames` фреймов входа
+    pub fn shift(&mut self, frames: usize) {
+        self.analysis -= frames as f64;
+        self.head -= frames as f64;
+        self.prev = self.prev.map(|p| p - frames);
+    }
+
+    /// Выдаёт один шаг выхода. Возвращает `false`, если входа не хватает.
+    pub fn process(&mut self, input: &VecDeque<f32>) -> bool {
+        let channels = self.channels;
+        let available = input.len() / channels;
+        let ideal = self.analysis.round() as usize;
+        let length = self.hop * 2;
+
+        let needed = match self.prev {
+            Some(prev) => (ideal + self.tolerance).max(prev + self.hop) + length,
+            None => ideal + length,
+        };
+        if available < needed {
+            return false;
+        }
+
+        let start = match self.prev {
+            Some(prev) => self.best_match(input, ideal, prev + self.hop),
+            None => ideal,
+        };
+
+        for frame in 0..self.hop {
+            for ch in 0..channels {
+                let sample = input[(start + frame) * channels + ch];
+                let value = match self.prev {
+                    Some(_) => self.overlap[frame * channels + ch] + sample * self.window[frame],
+                    // первый фрагмент без нарастания, чтобы не было провала
+                    None => sample,
+                };
+                self.out.push_back(value);
+            }
+        }
+        for frame in 0..self.hop {
+            for ch in 0..channels {
+                let sample = input[(start + self.hop + frame) * channels + ch];
+                self.overlap[frame * channels + ch] = sample * self.window[self.hop + frame];
+            }
+        }
+
+        self.prev = Some(start);
+        self.analysis += self.hop as f64 * self.tempo;
+        true
+    }
+
+    /// Входа пока больше нет: дописывает остаток как есть.
+    /// Половины окна Ханна в сумме дают единицу, поэтому хвост
+    /// после предыдущего фрагмента совпадает с исходными сэмплами.
+    pub fn finish(&mut self, input: &VecDeque<f32>) {
+        let channels = self.channels;
+        let start = self
+            .prev
+            .map_or(self.analysis.round() as usize, |p| p + self.hop);
+        let available = input.len() / channels;
+        if start < available {
+            self.out
+                .extend(input.range(start * channels..available * channels));
+        }
+
+        // новый вход, если появится, начнётся с чистого фрагмента
+        self.analysis = self.analysis.max(available as f64);
+        self.prev = None;
+        self.overlap.fill(0.0);
+    }
+
+    /// Ищет возле `ideal` фрагмент, лучше всего продолжающий `natural`
+    /// (нормированная взаимная корреляция по сумме каналов)
+    fn best_match(&self, input: &VecDeque<f32>, ideal: usize, natural: usize) -> usize {
+        let channels = self.channels;
+        let mono =
+            |frame: usize| -> f32 { (0..channels).map(|ch| input[frame * channels + ch]).sum() };
+
+        let from = ideal.saturating_sub(self.tolerance);
+        let to = ideal + self.tolerance;
+        let mut best = (ideal, f32::MIN);
+
+        for candidate in from..=to {
+            let (mut corr, mut energy) = (0.0f32, 1e-9f32);
+            // каждый второй фрейм: точности хватает, работы вдвое меньше
+            for i in (0..self.hop).step_by(2) {
+                let x = mono(candidate + i);
+                corr += x * mono(natural + i);
+                energy += x * x;
+            }
+            let score = corr / energy.sqrt();
+            if score > best.1 {
+                best = (candidate, score);
+            }
+        }
+        best.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changes_duration_by_tempo() {
+        let rate = 8000;
+        let input: VecDeque<f32> = (0..rate * 2)
+            .map(|i| (i as f32 * 440.0 * 2.0 * PI / rate as f32).sin())
+            .collect();
+
+        let mut stretcher = Stretcher::new(1, rate as u32, 2.0);
+        while stretcher.process(&input) {}
+
+        // без хвоста, который ещё не набрал целого окна
+        let produced = stretcher.output().len() as f32;
+        assert!(produced <= rate as f32 && produced > rate as f32 * 0.9);
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+use super::channels::ChannelLayout;
+use super::gain::ReplayGain;
+
+/// Неизменные сведения о треке, которые нужны интерфейсу
+#[derive(Debug)]
+pub struct TrackInfo {
+    pub path: String,
+    /// Точная длительность, если контейнер её знает, секунды
+    pub duration: Option<f64>,
+    pub layout: ChannelLayout,
+    pub album: Option<String>,
+    pub replay_gain: ReplayGain,
+}
+
+/// Отметка в кольцевом буфере: с фрейма `frame` звучит `track`
+/// с позиции `time`, каждый следующий фрейм сдвигает её на `step`
+#[derive(Clone, Debug)]
+pub struct Marker {
+    pub frame: u64,
+    pub track: Arc<TrackInfo>,
+    pub time: f64,
+    /// Секунды трека на один фрейм вывода, учитывает скорость
+    pub step: f64,
+}
+
+/// Позиция воспроизведения для интерфейса
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub path: String,
+    pub position: f64,
+    pub duration: Option<f64>,
+}
+
+/// Сопоставляет фреймы, отданные устройству, с позицией в треке.
+/// Поток декодирования пишет отметки, колбэк — только атомики.
+#[derive(Default)]
+pub struct Timeline {
+    markers: Mutex<VecDeque<Marker>>,
+    /// Сколько фреймов устройство забрало из буфера к началу последнего колбэка
+    played: AtomicU64,
+    /// Задержка устройства между колбэком и выходом звука, фреймы
+    latency: AtomicU64,
+    /// Сколько фреймов декодер записал в кольцевой буфер
+    written: AtomicU64,
+}
+
+impl Timeline {
+    /// Вызывается из аудиоколбэка
+    pub fn set_played(&self, frame: u64, latency: u64) {
+        self.played.store(frame, Ordering::Release);
+        self.latency.store(latency, Ordering::Relaxed);
+    }
+
+    /// Вызывается из потока декодирования после записи в буфер
+    pub fn set_written(&self, frame: u64) {
+        self.written.store(frame, Ordering::Relaxed);
+    }
+
+    /// Сколько фреймов записано, но ещё не прозвучало
+    pub fn buffered(&self) -> u64 {
+        self.written
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.heard())
+    }
+
+    /// Фрейм, который слышно прямо сейчас
+    pub fn heard(&self) -> u64 {
+        let played = self.played.load(Ordering::Acquire);
+        played.saturating_sub(self.latency.load(Ordering::Relaxed))
+    }
+
+    /// Добавляет отметки и забывает те, что уже отзвучали
+    pub fn record(&self, new: impl IntoIterator<Item = Marker>) {
+        let heard = self.heard();
+        let Ok(mut markers) = self.markers.lock() else {
+            return;
+        };
+        markers.extend(new);
+        while markers.len() > 1 && markers[1].frame <= heard {
+            markers.pop_front();
+        }
+    }
+
+    /// Начинает отсчёт заново после сброса буфера
+    pub fn reset(&self, marker: Marker) {
+        self.played.store(marker.frame, Ordering::Release);
+        self.written.store(marker.frame, Ordering::Relaxed);
+        if let Ok(mut markers) = self.markers.lock() {
+            markers.clear();
+            markers.push_back(marker);
+        }
+    }
+
+    pub fn position(&self) -> Option<(Arc<TrackInfo>, f64)> {
+        let heard = self.heard();
+        let markers = self.markers.lock().ok()?;
+        // до первой отметки звучит то, с чего она начинается
+        let marker = markers
+            .iter()
+            .rev()
+            .find(|m| m.frame <= heard)
+            .or(markers.front())?;
+
+        let offset = heard.saturating_sub(marker.frame) as f64 * marker.step;
+        let time = match marker.track.duration {
+            Some(duration) => (marker.time + offset).min(duration),
+            None => marker.time + offset,
+        };
+        Some((marker.track.clone(), time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::player::channels::MixMode;
+
+    fn track(path: &str) -> Arc<TrackInfo> {
+        Arc::new(TrackInfo {
+            path: path.to_string(),
+            duration: None,
+            layout: ChannelLayout {
+                source: "stereo".to_string(),
+                source_channels: 2,
+                output_channels: 2,
+                mode: MixMode::Passthrough,
+            },
+            album: None,
+            replay_gain: ReplayGain::default(),
+        })
+    }
+
+    #[test]
+    fn position_follows_played_frames_and_latency() {
+        let timeline = Timeline::default();
+        let (a, b) = (track("a"), track("b"));
+        timeline.reset(Marker {
+            frame: 0,
+            track: a,
+            time: 10.0,
+            step: 0.001,
+        });
+        timeline.record([Marker {
+            frame: 1000,
+            track: b,
+            time: 0.0,
+            step: 0.002,
+        }]);
+
+        timeline.set_played(500, 0);
+        let (track, time) = timeline.position().unwrap();
+        assert_eq!(track.path, "a");
+        assert!((time - 10.5).abs() < 1e-9);
+
+        // задержка устройства: второй трек ещё не слышно
+        timeline.set_played(1100, 200);
+        assert_eq!(timeline.position().unwrap().0.path, "a");
+
+        timeline.set_played(1500, 0);
+        let (track, time) = timeline.position().unwrap();
+        assert_eq!(track.path, "b");
+        assert!((time - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,501 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// Режим повтора
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RepeatMode {
+    #[default]
+    Off,
+    /// Трек повторяется, пока его не переключат вручную
+    One,
+    All,
+}
+
+/// Элемент очереди; `id` различает одинаковые файлы, добавленные дважды
+#[derive(Clone, Debug, Serialize)]
+pub struct QueueEntry {
+    pub id: u64,
+    pub path: String,
+}
+
+/// Снимок очереди для интерфейса
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueState {
+    pub tracks: Vec<QueueEntry>,
+    /// Порядок воспроизведения; при перемешивании отличается от `tracks`
+    pub order: Vec<u64>,
+    pub current: Option<u64>,
+    pub repeat: RepeatMode,
+    pub shuffle: bool,
+}
+
+/// Очередь для сохранения между запусками: ссылки на элементы —
+/// индексы в `paths`, потому что `id` после перезапуска выдаются заново
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SavedQueue {
+    pub paths: Vec<String>,
+    pub current: Option<usize>,
+    pub pending: Option<usize>,
+    /// Порядок перемешивания; `None` — перемешивание выключено
+    pub shuffled: Option<Vec<usize>>,
+    pub repeat: RepeatMode,
+}
+
+/// Очередь воспроизведения. Перемешивание — перестановка Фишера — Йетса,
+/// которая сохраняется, пока его не выключат, поэтому «назад» возвращает
+/// к уже сыгранным трекам, а трек не повторяется до конца круга.
+#[derive(Clone)]
+pub struct Queue {
+    tracks: Vec<QueueEntry>,
+    shuffled: Option<Vec<u64>>,
+    current: Option<u64>,
+    /// Текущий трек удалили: следующим станет этот, без сдвига
+    pending: Option<u64>,
+    repeat: RepeatMode,
+    next_id: u64,
+    seed: u64,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+        Queue::with_seed(seed)
+    }
+}
+
+impl Queue {
+    pub fn with_seed(seed: u64) -> Self {
+        Queue {
+            tracks: Vec::new(),
+            shuffled: None,
+            current: None,
+            pending: None,
+            repeat: RepeatMode::Off,
+            next_id: 0,
+            // нулевое состояние xorshift так и осталось бы нулём
+            seed: seed | 1,
+        }
+    }
+
+    pub fn state(&self) -> QueueState {
+        QueueState {
+            tracks: self.tracks.clone(),
+            order: self.order(),
+            current: self.current,
+            repeat: self.repeat,
+            shuffle: self.shuffled.is_some(),
+        }
+    }
+
+    pub fn current(&self) -> Option<&str> {
+        self.current.and_then(|id| self.path(id))
+    }
+
+    /// Играет ли очередь трек `path`: он текущий, или текущий удалили
+    /// во время игры и дальше очередь продолжит с отложенного
+    pub fn follows(&self, path: &str) -> bool {
+        match self.current {
+            Some(_) => self.current() == Some(path),
+            None => self.pending.is_some(),
+        }
+    }
+
+    pub fn set_repeat(&mut self, repeat: RepeatMode) {
+        self.repeat = repeat;
+    }
+
+    pub fn save(&self) -> SavedQueue {
+        SavedQueue {
+            paths: self.tracks.iter().map(|e| e.path.clone()).collect(),
+            current: self.current.and_then(|id| self.index(id)),
+            pending: self.pending.and_then(|id| self.index(id)),
+            shuffled: self
+                .shuffled
+                .as_ref()
+                .map(|order| order.iter().filter_map(|&id| self.index(id)).collect()),
+            repeat: self.repeat,
+        }
+    }
+
+    /// Восстанавливает сохранённую очередь. Испорченный порядок
+    /// перемешивания не переносится, а строится заново.
+    pub fn restore(&mut self, saved: SavedQueue) {
+        self.tracks = self.entries(saved.paths);
+        let id = |index: Option<usize>, tracks: &[QueueEntry]| {
+            index.and_then(|i| tracks.get(i)).map(|e| e.id)
+        };
+        self.current = id(saved.current, &self.tracks);
+        self.pending = id(saved.pending, &self.tracks);
+        self.repeat = saved.repeat;
+        self.shuffled = None;
+
+        if let Some(order) = saved.shuffled {
+            let mut sorted = order.clone();
+            sorted.sort_unstable();
+            if sorted.into_iter().eq(0..self.tracks.len()) {
+                self.shuffled = Some(order.into_iter().map(|i| self.tracks[i].id).collect());
+            } else {
+                self.reshuffle();
+            }
+        }
+    }
+
+    /// Заменяет очередь и делает текущим трек `start`
+    pub fn replace(&mut self, paths: Vec<String>, start: usize) -> Option<&str> {
+        self.tracks.clear();
+        self.pending = None;
+        let entries = self.entries(paths);
+        self.current = entries.get(start).or(entries.first()).map(|e| e.id);
+        self.tracks = entries;
+        if self.shuffled.is_some() {
+            self.reshuffle();
+        }
+        self.current()
+    }
+
+    /// Добавляет треки в конец; при перемешивании — в случайные места
+    /// среди ещё не сыгранных
+    pub fn add(&mut self, paths: Vec<String>) {
+        let entries = self.entries(paths);
+        let ids: Vec<u64> = entries.iter().map(|e| e.id).collect();
+        self.tracks.extend(entries);
+
+        let played = self.played();
+        if let Some(order) = &mut self.shuffled {
+            for id in ids {
+                let slot = played + xorshift(&mut self.seed) as usize % (order.len() - played + 1);
+                order.insert(slot, id);
+            }
+        }
+    }
+
+    /// Ставит треки сразу после текущего в обоих порядках
+    pub fn play_next(&mut self, paths: Vec<String>) {
+        let entries = self.entries(paths);
+        let ids: Vec<u64> = entries.iter().map(|e| e.id).collect();
+        let at = match (self.current, self.pending) {
+            (Some(id), _) => self.index(id).map_or(0, |index| index + 1),
+            // удалённый текущий: следующий ещё не начат, встаём перед ним
+            (None, Some(id)) => self.index(id).unwrap_or(0),
+            (None, None) => 0,
+        };
+        self.tracks.splice(at..at, entries);
+
+        let played = self.played();
+        if let Some(order) = &mut self.shuffled {
+            order.splice(played..played, ids);
+        }
+    }
+
+    /// Удаляет элемент; `true`, если это был текущий трек
+    pub fn remove(&mut self, id: u64) -> bool {
+        let Some(index) = self.index(id) else {
+            return false;
+        };
+        let order = self.order();
+        let position = order.iter().position(|&o| o == id);
+        let successor = position.and_then(|p| order.get(p + 1)).copied();
+
+        let was_current = self.current == Some(id);
+        if was_current {
+            self.current = None;
+            self.pending = successor;
+        } else if self.pending == Some(id) {
+            self.pending = successor;
+        }
+
+        self.tracks.remove(index);
+        if let Some(order) = &mut self.shuffled {
+            order.retain(|&o| o != id);
+        }
+        was_current
+    }
+
+    /// Перемещает элемент на позицию `to` в списке очереди
+    pub fn move_to(&mut self, id: u64, to: usize) -> bool {
+        let Some(from) = self.index(id) else {
+            return false;
+        };
+        let entry = self.tracks.remove(from);
+        let to = to.min(self.tracks.len());
+        self.tracks.insert(to, entry);
+        true
+    }
+
+    pub fn clear(&mut self) {
+        self.tracks.clear();
+        self.current = None;
+        self.pending = None;
+        if let Some(order) = &mut self.shuffled {
+            order.clear();
+        }
+    }
+
+    /// Включает перемешивание: текущий трек остаётся первым, остальные
+    /// переставляются; выключение возвращает порядок списка
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        match (shuffle, self.shuffled.is_some()) {
+            (true, false) => self.reshuffle(),
+            (false, true) => self.shuffled = None,
+            _ => {}
+        }
+    }
+
+    /// Переходит к следующему треку. `ended` — трек доиграл сам:
+    /// тогда повтор одного трека его повторяет, а ручное переключение нет.
+    pub fn next(&mut self, ended: bool) -> Option<&str> {
+        if ended && self.repeat == RepeatMode::One && self.current.is_some() {
+            return self.current();
+        }
+        if let Some(id) = self.pending.take() {
+            self.current = Some(id);
+            return self.current();
+        }
+
+        let order = self.order();
+        let next = match self
+            .current
+            .and_then(|id| order.iter().position(|&o| o == id))
+        {
+            Some(position) => order.get(position + 1).copied(),
+            None => order.first().copied(),
+        };
+        self.current = match next {
+            Some(id) => Some(id),
+            None if self.repeat != RepeatMode::Off && !order.is_empty() => {
+                if self.shuffled.is_some() {
+                    self.new_round();
+                }
+                self.order().first().copied()
+            }
+            None => return None,
+        };
+        self.current()
+    }
+
+    /// Трек, который включит `next(true)`, без перехода к нему.
+    /// Новый круг перемешивания тоже совпадёт: генератор копируется вместе с очередью.
+    pub fn peek_next(&self) -> Option<String> {
+        self.clone().next(true).map(str::to_string)
+    }
+
+    /// Возвращается к предыдущему в порядке воспроизведения
+    pub fn previous(&mut self) -> Option<&str> {
+        let order = self.order();
+        let position = self
+            .anchor()
+            .and_then(|id| order.iter().position(|&o| o == id));
+        let previous = match position {
+            Some(0) if self.repeat == RepeatMode::All => order.last().copied(),
+            Some(position) if position > 0 => order.get(position - 1).copied(),
+            _ => None,
+        };
+        if previous.is_some() {
+            self.current = previous;
+            self.pending = None;
+        }
+        previous.and_then(|id| self.path(id))
+    }
+
+    fn order(&self) -> Vec<u64> {
+        match &self.shuffled {
+            Some(order) => order.clone(),
+            None => self.tracks.iter().map(|e| e.id).collect(),
+        }
+    }
+
+    /// Сколько элементов порядка перемешивания уже сыграно, включая текущий
+    fn played(&self) -> usize {
+        let Some(order) = &self.shuffled else {
+            return 0;
+        };
+        match self
+            .anchor()
+            .and_then(|id| order.iter().position(|&o| o == id))
+        {
+            Some(position) if self.current.is_some() => position + 1,
+            Some(position) => position,
+            None => 0,
+        }
+    }
+
+    fn anchor(&self) -> Option<u64> {
+        self.current.or(self.pending)
+    }
+
+    fn reshuffle(&mut self) {
+        let first = self.anchor();
+        let mut rest: Vec<u64> = self
+            .tracks
+            .iter()
+            .map(|e| e.id)
+            .filter(|&id| Some(id) != first)
+            .collect();
+        shuffle(&mut rest, &mut self.seed);
+        self.shuffled = Some(first.into_iter().chain(rest).collect());
+    }
+
+    /// Новый круг при повторе всего: последний трек круга не идёт первым
+    fn new_round(&mut self) {
+        let last = self.current;
+        let mut order: Vec<u64> = self.tracks.iter().map(|e| e.id).collect();
+        shuffle(&mut order, &mut self.seed);
+        if order.len() > 1 && order.first().copied() == last {
+            let swap = 1 + xorshift(&mut self.seed) as usize % (order.len() - 1);
+            order.swap(0, swap);
+        }
+        self.current = None;
+        self.shuffled = Some(order);
+    }
+
+    fn entries(&mut self, paths: Vec<String>) -> Vec<QueueEntry> {
+        paths
+            .into_iter()
+            .map(|path| {
+                self.next_id += 1;
+                QueueEntry {
+                    id: self.next_id,
+                    path,
+                }
+            })
+            .collect()
+    }
+
+    fn index(&self, id: u64) -> Option<usize> {
+        self.tracks.iter().position(|e| e.id == id)
+    }
+
+    fn path(&self, id: u64) -> Option<&str> {
+        self.tracks
+            .iter()
+            .find(|e| e.id == id)
+            .map(|e| e.path.as_str())
+    }
+}
+
+/// Перестановка Фишера — Йетса
+fn shuffle(ids: &mut [u64], seed: &mut u64) {
+    for i in (1..ids.len()).rev() {
+        let j = xorshift(seed) as usize % (i + 1);
+        ids.swap(i, j);
+    }
+}
+
+/// Псевдослучайное число (xorshift64)
+fn xorshift(seed: &mut u64) -> u64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    *seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("{i}.mp3")).collect()
+    }
+
+    #[test]
+    fn repeat_modes() {
+        let mut queue = Queue::with_seed(1);
+        assert_eq!(queue.replace(paths(2), 0), Some("0.mp3"));
+        assert_eq!(queue.next(true), Some("1.mp3"));
+        assert_eq!(queue.next(true), None);
+
+        queue.set_repeat(RepeatMode::One);
+        assert_eq!(queue.next(true), Some("1.mp3"));
+        assert_eq!(queue.next(false), Some("0.mp3"));
+
+        queue.set_repeat(RepeatMode::All);
+        assert_eq!(queue.previous(), Some("1.mp3"));
+    }
+
+    #[test]
+    fn shuffle_plays_each_once_and_goes_back() {
+        let mut queue = Queue::with_seed(42);
+        queue.replace(paths(10), 3);
+        queue.set_shuffle(true);
+
+        let mut played = vec![queue.current().unwrap().to_string()];
+        while let Some(path) = queue.next(true) {
+            played.push(path.to_string());
+        }
+        assert_eq!(played[0], "3.mp3");
+        let mut sorted = played.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 10);
+
+        assert_eq!(queue.previous(), Some(played[8].as_str()));
+        assert_eq!(queue.previous(), Some(played[7].as_str()));
+    }
+
+    #[test]
+    fn restores_saved_order() {
+        let mut queue = Queue::with_seed(3);
+        queue.replace(paths(5), 2);
+        queue.set_shuffle(true);
+        queue.set_repeat(RepeatMode::All);
+        queue.next(false);
+        let saved = queue.save();
+
+        let mut restored = Queue::with_seed(9);
+        restored.restore(saved.clone());
+        assert_eq!(restored.save(), saved);
+        assert_eq!(restored.current(), queue.current());
+        assert_eq!(restored.next(false), queue.next(false));
+
+        let mut broken = saved;
+        broken.shuffled = Some(vec![0, 0, 1]);
+        restored.restore(broken);
+        assert_eq!(restored.state().order.len(), 5);
+    }
+
+    #[test]
+    fn peek_matches_next() {
+        let mut queue = Queue::with_seed(11);
+        queue.replace(paths(4), 0);
+        queue.set_shuffle(true);
+        queue.set_repeat(RepeatMode::All);
+        // два круга, включая переход между ними
+        for _ in 0..8 {
+            let peeked = queue.peek_next();
+            assert_eq!(queue.next(true).map(str::to_string), peeked);
+        }
+    }
+
+    #[test]
+    fn removed_current_plays_to_end_then_continues() {
+        let mut queue = Queue::with_seed(5);
+        queue.replace(paths(3), 0);
+        queue.remove(queue.state().current.unwrap());
+
+        // удалённый трек доигрывает, после него очередь идёт дальше
+        assert!(queue.follows("0.mp3"));
+        assert_eq!(queue.next(true), Some("1.mp3"));
+        assert!(queue.follows("1.mp3"));
+        assert!(!queue.follows("0.mp3"));
+    }
+
+    #[test]
+    fn removing_current_continues_with_next() {
+        let mut queue = Queue::with_seed(7);
+        queue.replace(paths(3), 1);
+        queue.play_next(vec!["next.mp3".to_string()]);
+        let current = queue.state().current.unwrap();
+
+        assert!(queue.remove(current));
+        assert_eq!(queue.current(), None);
+        assert_eq!(queue.next(false), Some("next.mp3"));
+        assert_eq!(queue.next(false), Some("2.mp3"));
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::queue::SavedQueue;
+
+/// Что играло при закрытии; при следующем запуске восстанавливается на паузе
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Session {
+    pub path: Option<String>,
+    /// Позиция в треке, секунды
+    pub position: f64,
+    pub volume: f32,
+    pub speed: f32,
+    pub queue: SavedQueue,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            path: None,
+            position: 0.0,
+            volume: 1.0,
+            speed: 1.0,
+            queue: SavedQueue::default(),
+        }
+    }
+}
+
+/// Файл сеанса и последнее записанное в него состояние
+pub struct SessionStore {
+    path: PathBuf,
+    enabled: bool,
+    saved: Option<Session>,
+}
+
+impl SessionStore {
+    pub fn new(path: PathBuf, enabled: bool) -> Self {
+        SessionStore {
+            path,
+            enabled,
+            saved: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Читает сеанс с диска; при выключенном восстановлении, повреждённом
+    /// или отсутствующем файле — `None`
+    pub fn load(&mut self) -> Option<Session> {
+        if !self.enabled {
+            return None;
+        }
+        let session: Session = fs::read(&self.path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())?;
+        self.saved = Some(session.clone());
+        Some(session)
+    }
+
+    /// Записывает сеанс, если он изменился с прошлой записи.
+    /// Пишет во временный файл и переименовывает, чтобы не оставить половину.
+    pub fn save(&mut self, session: Session) -> io::Result<()> {
+        if !self.enabled || self.saved.as_ref() == Some(&session) {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = serde_json::to_vec(&session).map_err(io::Error::other)?;
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, data)?;
+        fs::rename(temp, &self.path)?;
+        self.saved = Some(session);
+        Ok(())
+    }
+
+    /// Выключение заодно стирает сохранённый сеанс
+    pub fn set_enabled(&mut self, enabled: bool) -> io::Result<()> {
+        self.enabled = enabled;
+        if enabled {
+            return Ok(());
+        }
+        self.saved = None;
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_get_defaults() {
+        let session: Session = serde_json::from_str(r#"{"path": "a.mp3"}"#).unwrap();
+        assert_eq!(session.path.as_deref(), Some("a.mp3"));
+        assert_eq!(session.volume, 1.0);
+        assert_eq!(session.speed, 1.0);
+        assert!(session.queue.paths.is_empty());
+    }
+}
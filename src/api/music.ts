@@ -1,44 +1,71 @@
-import { invoke } from '@tauri-apps/api/core';
+import { convertFileSrc, invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { Music } from '../types/music';
 
+/** Ошибка любой команды: `messageKey` — ключ перевода, `details` — для журнала */
+export type CommandError = {
+	code: string;
+	messageKey: string;
+	details: string;
+};
+
+export const isCommandError = (error: unknown): error is CommandError =>
+	typeof error === 'object' &&
+	error !== null &&
+	'code' in error &&
+	'messageKey' in error;
+
+export type TrackMetadata = {
+	title: string;
+	artists: string[];
+	albumArtist: string | null;
+	album: string | null;
+	trackNumber: number | null;
+	trackTotal: number | null;
+	discNumber: number | null;
+	discTotal: number | null;
+	year: number | null;
+	date: string | null;
+	genres: string[];
+	composer: string | null;
+	comment: string | null;
+	replayGainTrack: number | null;
+	replayGainAlbum: number | null;
+	durationMs: number;
+	bitrate: number | null;
+	sampleRate: number | null;
+	channels: number | null;
+	codec: string | null;
+	bitDepth: number | null;
+	cover: string | null;
+	extras: Record<string, string>;
+};
+
 export type MusicFile = {
 	path: string;
-	tags: {
-		EncoderSettings: string | null;
-		TrackArtist: string | null;
-		TrackTitle: string | null;
-		album: null;
-		artist: string | null;
-		duration_ms: 278018;
-		genre: string | null;
-		title: string | null;
-		cover: string | null;
-	};
+	metadata: TrackMetadata;
 };
 
+/** Миниатюра обложки из дискового кэша приложения */
+export const coverUrl = (cover: string | null, size = 256) =>
+	cover ? convertFileSrc(`${cover}/${size}`, 'cover') : null;
+
 export const getMusics = async (
 	paths: string[] = ['C:/Users/Public/Music']
 ) => {
 	const musicFiles = await invoke<MusicFile[]>('get_musics', { paths });
 
-	const musics = musicFiles.map<Music>((musicFile, i) => {
-		const title =
-			musicFile.tags.title ||
-			musicFile.tags.TrackTitle ||
-			musicFile.path.split('/').pop() ||
-			`Music ${i + 1}`;
-		return {
-			id: i,
-			path: musicFile.path,
-			title: title,
-			genre: musicFile.tags.genre,
-			album: musicFile.tags.album,
-			artist: musicFile.tags.artist || musicFile.tags.TrackArtist,
-			duration: musicFile.tags.duration_ms,
-			cover: musicFile.tags.cover,
-			url: null,
-		};
-	});
+	const musics = musicFiles.map<Music>(({ path, metadata }, i) => ({
+		id: i,
+		path,
+		title: metadata.title || path.split('/').pop() || `Music ${i + 1}`,
+		genre: metadata.genres.join(', ') || null,
+		album: metadata.album,
+		artist: metadata.artists.join(', ') || metadata.albumArtist,
+		duration: metadata.durationMs,
+		cover: coverUrl(metadata.cover),
+		url: null,
+	}));
 	return musics;
 };
 
@@ -52,6 +79,178 @@ export const stopMusic = async () => await invoke('stop_music');
 export const setVolume = async (volume: number) =>
 	await invoke('set_volume', { volume });
 
+export type GainMode = 'off' | 'track' | 'album' | 'auto';
+
+export type ReplayGainSettings = {
+	mode: GainMode;
+	/** добавка к усилению, дБ */
+	preamp: number;
+};
+
+export const getReplayGain = async () =>
+	await invoke<ReplayGainSettings>('get_replay_gain');
+
+/** Меняет режим, сохраняя предусилитель */
+export const setReplayGain = async (mode: GainMode) => {
+	const { preamp } = await getReplayGain();
+	await invoke('set_replay_gain', { replayGain: { mode, preamp } });
+};
+
+export type FadeCurve = 'linear' | 'equal-power' | 's-curve';
+
+export type Crossfade = {
+	/** при естественном окончании трека, секунды; 0 — выключено */
+	duration: number;
+	curve: FadeCurve;
+	/** при ручном переключении, секунды */
+	skipDuration: number;
+};
+
+export const getCrossfade = async () =>
+	await invoke<Crossfade>('get_crossfade');
+
+/** Меняет длительность, сохраняя кривую и переход при переключении */
+export const setCrossfade = async (duration: number) => {
+	const crossfade = await getCrossfade();
+	await invoke('set_crossfade', { crossfade: { ...crossfade, duration } });
+};
+
+export type CoverCacheStats = {
+	entries: number;
+	bytes: number;
+	budget: number;
+	hits: number;
+	misses: number;
+	evictions: number;
+	folders: number;
+};
+
+export const getCoverCacheStats = async () =>
+	await invoke<CoverCacheStats>('get_cover_cache_stats');
+
+export const setCoverCacheBudget = async (budget: number) =>
+	await invoke<CoverCacheStats>('set_cover_cache_budget', { budget });
+
+export type CoverRules = {
+	names: string[];
+	extensions: string[];
+	subfolders: string[];
+	largestFallback: boolean;
+};
+
+export const getCoverRules = async () =>
+	await invoke<CoverRules>('get_cover_rules');
+
+export const setCoverRules = async (rules: CoverRules) =>
+	await invoke('set_cover_rules', { rules });
+
+/** 'keep' оставляет у каждого трека его значение, 'clear' удаляет поле */
+export type FieldEdit<T> = 'keep' | 'clear' | { set: T };
+
+export type TagEdit = Partial<{
+	title: FieldEdit<string>;
+	artists: FieldEdit<string[]>;
+	album: FieldEdit<string>;
+	albumArtist: FieldEdit<string>;
+	trackNumber: FieldEdit<number>;
+	genres: FieldEdit<string[]>;
+	year: FieldEdit<number>;
+	comment: FieldEdit<string>;
+	/** путь к файлу картинки */
+	cover: FieldEdit<string>;
+}>;
+
+export type TagEditSummary = {
+	updated: MusicFile[];
+	failed: { path: string; error: string }[];
+};
+
+export const editTags = async (paths: string[], edit: TagEdit) =>
+	await invoke<TagEditSummary>('edit_tags', { paths, edit });
+
+export type RepeatMode = 'off' | 'one' | 'all';
+
+export type QueueState = {
+	tracks: { id: number; path: string }[];
+	order: number[];
+	current: number | null;
+	repeat: RepeatMode;
+	shuffle: boolean;
+};
+
+export const getQueue = async () => await invoke<QueueState>('get_queue');
+
+export const setQueue = async (paths: string[], start: number) =>
+	await invoke<string | null>('set_queue', { paths, start });
+
+export const queueAdd = async (paths: string[]) =>
+	await invoke('queue_add', { paths });
+
+export const queuePlayNext = async (paths: string[]) =>
+	await invoke('queue_play_next', { paths });
+
+export const queueRemove = async (id: number) =>
+	await invoke('queue_remove', { id });
+
+export const queueMove = async (id: number, to: number) =>
+	await invoke('queue_move', { id, to });
+
+export const queueClear = async () => await invoke('queue_clear');
+
+export const queueNext = async () => await invoke<string | null>('queue_next');
+
+export const queuePrevious = async () =>
+	await invoke<string | null>('queue_previous');
+
+export const setRepeat = async (repeat: RepeatMode) =>
+	await invoke('set_repeat', { repeat });
+
+export const setShuffle = async (shuffle: boolean) =>
+	await invoke('set_shuffle', { shuffle });
+
+export type EndReason = 'finished' | 'decodeError' | 'deviceLost';
+
+export type TrackEnded = {
+	path: string;
+	reason: EndReason;
+	// что играет после перехода; null — воспроизведение остановилось
+	next: string | null;
+};
+
+export const onTrackEnded = async (handler: (ended: TrackEnded) => void) =>
+	await listen<TrackEnded>('track-ended', (event) => handler(event.payload));
+
+export type PlaybackState = {
+	path: string | null;
+	position: number;
+	duration: number | null;
+	paused: boolean;
+	ended: boolean;
+	volume: number;
+	speed: number;
+	buffered: number;
+};
+
+export const getPlayerState = async () =>
+	await invoke<PlaybackState | null>('get_player_state');
+
+export const onPlaybackState = async (
+	handler: (state: PlaybackState) => void
+) =>
+	await listen<PlaybackState>('playback-state', (event) =>
+		handler(event.payload)
+	);
+
+export const setPlaybackStateInterval = async (interval: number) =>
+	await invoke('set_playback_state_interval', { interval });
+
+/** Восстанавливать ли при запуске трек, позицию и очередь прошлого сеанса */
+export const getResumeSession = async () =>
+	await invoke<boolean>('get_resume_session');
+
+export const setResumeSession = async (enabled: boolean) =>
+	await invoke('set_resume_session', { enabled });
+
 export const setTime = async (ms: number) => {
 	return await invoke('seek_music', { sec: ms * 0.001 });
 };
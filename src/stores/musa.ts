@@ -8,6 +8,16 @@ import {
 	setTime as sT,
 	getMusics,
 	getTime,
+	getPlayerState,
+	getQueue,
+	PlaybackState,
+	setQueue,
+	queueNext,
+	queuePrevious,
+	setRepeat,
+	RepeatMode,
+	setShuffle,
+	TrackEnded,
 } from '../api/music';
 import { load } from '@tauri-apps/plugin-store';
 
@@ -16,7 +26,7 @@ export type Musa = {
 	musicList: Music[];
 	isPlaying: boolean;
 	volume: number;
-	repeat: boolean;
+	repeat: RepeatMode;
 	shuffle: boolean;
 	index: number;
 	time: number;
@@ -31,25 +41,24 @@ export const useMusa = defineStore('musa', {
 		isPlaying: false,
 		time: 0,
 		volume: 100,
-		repeat: false,
+		repeat: 'off',
 		shuffle: false,
 		index: 0,
 		playlist: 'all',
 		playlists: [],
 	}),
 	actions: {
-		nextMusic() {
-			if (this.shuffle) {
-				this.index = Math.floor(Math.random() * this.musicList.length);
-			} else {
-				this.index = (this.index + 1) % this.musicList.length;
-			}
-			this.setMusic(this.musicList[this.index]);
+		async nextMusic() {
+			this.showQueued(await queueNext());
 		},
-		prevMusic() {
-			this.index =
-				(this.index - 1 + this.musicList.length) % this.musicList.length;
-			this.setMusic(this.musicList[this.index]);
+		async prevMusic() {
+			this.showQueued(await queuePrevious());
+		},
+		showQueued(path: string | null) {
+			const music = this.musicList.find((m) => m.path === path);
+			if (music) {
+				this.showMusic(music);
+			}
 		},
 		setMusics(musics: Music[]) {
 			this.musicList = musics;
@@ -62,13 +71,35 @@ export const useMusa = defineStore('musa', {
 			this.volume = volume;
 		},
 		async setMusic(music: Music) {
-			await setM(music.path);
+			const index = this.musicList.indexOf(music);
+			if (index >= 0) {
+				// очередь живёт в Rust: переключение работает и без окна
+				await setQueue(this.musicList.map((m) => m.path), index);
+			} else {
+				await setM(music.path);
+			}
+			this.showMusic(music);
+		},
+		showMusic(music: Music) {
 			this.setVolume(this.volume);
 			this.music = music;
 			this.time = 0;
 			this.index = this.musicList.indexOf(music);
 			this.isPlaying = true;
 		},
+		// прошлый сеанс бэкенд уже открыл на паузе; `false` — показывать нечего
+		async restoreSession() {
+			const [state, queue] = await Promise.all([getPlayerState(), getQueue()]);
+			this.repeat = queue.repeat;
+			this.shuffle = queue.shuffle;
+			const music = this.musicList.find((m) => m.path === state?.path);
+			if (!state || !music) return false;
+			this.music = music;
+			this.index = this.musicList.indexOf(music);
+			this.volume = Math.round(state.volume * 100);
+			this.applyState(state);
+			return true;
+		},
 		async playMusic() {
 			this.isPlaying = true;
 			await pM();
@@ -77,38 +108,25 @@ export const useMusa = defineStore('musa', {
 			this.isPlaying = false;
 			await sM();
 		},
-		async playing() {
-			if (
-				this.music &&
-				this.music.duration &&
-				this.music.duration >= this.time &&
-				this.isPlaying
-			) {
-				const currentTime = await getTime();
-
-				this.time = Math.floor(currentTime * 1000);
-				return true;
-			} else {
-				if (
-					this.music &&
-					this.music.duration &&
-					this.music.duration <= this.time
-				) {
-					await this.finish();
-				}
-				return false;
-			}
+		// состояние присылает бэкенд: событием во время игры и снимком по фокусу
+		applyState(state: PlaybackState | null) {
+			if (!state) return;
+			this.time = Math.floor(state.position * 1000);
+			this.isPlaying = !state.paused && !state.ended;
 		},
 		async timeSeek(time: number) {
 			await sT(time);
 			const currentTime = await getTime();
 			this.time = Math.floor(currentTime * 1000);
 		},
-		async finish() {
-			if (this.repeat && this.music) {
-				await this.setMusic(this.music);
+		// к следующему треку очередь переходит сама, в Rust
+		trackEnded({ next }: TrackEnded) {
+			if (next === null) {
+				this.isPlaying = false;
+			} else if (next === this.music?.path) {
+				this.time = 0;
 			} else {
-				this.nextMusic();
+				this.showQueued(next);
 			}
 		},
 		async removeMusicFromPlaylist(music: Music) {
@@ -166,10 +184,18 @@ export const useMusa = defineStore('musa', {
 				...newPlalylistItem,
 			});
 		},
-		toggleRepeat() {
-			this.repeat = !this.repeat;
+		// выключен → вся очередь → один трек → выключен
+		async toggleRepeat() {
+			const next: Record<RepeatMode, RepeatMode> = {
+				off: 'all',
+				all: 'one',
+				one: 'off',
+			};
+			await setRepeat(next[this.repeat]);
+			this.repeat = next[this.repeat];
 		},
-		toggleShuffle() {
+		async toggleShuffle() {
+			await setShuffle(!this.shuffle);
 			this.shuffle = !this.shuffle;
 		},
 		async getCurrentPlaylist() {
@@ -4,7 +4,7 @@ export const getUrl = (url: string): string => {
 	if (url && (url.includes('http') || url.includes('https'))) {
 		return url;
 	}
-	if (url.startsWith('data:')) {
+	if (url.startsWith('data:') || url.startsWith('cover:')) {
 		return url;
 	}
 	return joinUrl(BASE_URL, url);